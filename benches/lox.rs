@@ -0,0 +1,109 @@
+//! Runs every script in `benches/lox` and compares the results with a
+//! baseline saved by an earlier run.
+//!
+//! ```text
+//! cargo bench [-- [filter] [--baseline NAME] [--save-baseline NAME] [--threshold PERCENT] [--iterations N]]
+//! ```
+//!
+//! Every run is saved as the `last` baseline, which is also what runs are
+//! compared against by default. Baselines live in `target/lox-bench`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use tapssp_project::benchmark::{self, Baseline};
+
+struct Options {
+    filter: Option<String>,
+    baseline: String,
+    save: Option<String>,
+    threshold: f64,
+    iterations: usize,
+}
+
+fn options() -> Options {
+    let mut options = Options { filter: None, baseline: "last".to_string(), save: None, threshold: 0.15, iterations: 5 };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| {
+            eprintln!("Missing value for {}", arg);
+            process::exit(2);
+        });
+
+        match arg.as_str() {
+            "--baseline" => options.baseline = value(),
+            "--save-baseline" => options.save = Some(value()),
+            "--threshold" => options.threshold = value().parse::<f64>().expect("Invalid threshold") / 100.0,
+            "--iterations" => options.iterations = value().parse().expect("Invalid iteration count"),
+            // Passed by `cargo bench` to every bench target.
+            "--bench" => {},
+            _ => options.filter = Some(arg),
+        }
+    }
+
+    options
+}
+
+fn load(store: &Path, name: &str) -> Baseline {
+    let path = store.join(format!("{}.json", name));
+    match fs::read_to_string(&path) {
+        Ok(text) => Baseline::from_json(&text).unwrap_or_else(|e| {
+            eprintln!("Ignoring unreadable baseline {}: {}", path.display(), e);
+            Baseline::default()
+        }),
+        Err(_) => Baseline::default(),
+    }
+}
+
+fn main() {
+    let options = options();
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let store = root.join("target").join("lox-bench");
+
+    let mut scripts: Vec<PathBuf> = fs::read_dir(root.join("benches").join("lox"))
+        .expect("Could not read benches/lox")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+
+    let mut current = Baseline::default();
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        if options.filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue;
+        }
+
+        let source = fs::read_to_string(&path).expect("Could not read benchmark");
+        match benchmark::measure(&source, options.iterations) {
+            Ok(measurement) => { current.0.insert(name, measurement); },
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                process::exit(1);
+            },
+        }
+    }
+
+    let baseline = load(&store, &options.baseline);
+    let comparisons = benchmark::compare(&current, &baseline);
+    print!("{}", benchmark::report(&comparisons, options.threshold));
+
+    // Filtered runs only replace the benchmarks they ran.
+    fs::create_dir_all(&store).expect("Could not create target/lox-bench");
+    for name in std::iter::once("last").chain(options.save.as_deref()) {
+        let mut saved = load(&store, name);
+        saved.0.extend(current.0.clone());
+
+        let path = store.join(format!("{}.json", name));
+        if let Err(e) = fs::write(&path, saved.to_json()) {
+            eprintln!("Could not write {}: {}", path.display(), e);
+        }
+    }
+
+    let regressions = comparisons.iter().filter(|c| c.is_regression(options.threshold)).count();
+    if regressions > 0 {
+        eprintln!("\n{} benchmarks regressed by more than {}% against '{}'", regressions, options.threshold * 100.0, options.baseline);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,54 @@
+//! Compiles arbitrary sequences of tokens. Sources that compile without
+//! diagnostics must also compile for the VM, and their bytecode must
+//! disassemble.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tapssp_project::{compiler::{analyze, compile}, disassembler::disassemble};
+
+#[derive(Arbitrary, Debug)]
+enum Token {
+    LeftParen, RightParen, LeftBrace, RightBrace,
+    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    Bang, BangEqual, Equal, EqualEqual,
+    Greater, GreaterEqual, Less, LessEqual,
+    Identifier(u8), String(u8), Number(u16),
+    And, Class, Else, False, Fun, For, If, Nil, Or,
+    Print, Return, Super, This, True, Var, While,
+    Test, Newline,
+}
+
+const NAMES: [&str; 6] = ["a", "b", "f", "test", "clock", "assert"];
+
+fn lexeme(token: &Token) -> String {
+    let text = match token {
+        Token::LeftParen => "(", Token::RightParen => ")",
+        Token::LeftBrace => "{", Token::RightBrace => "}",
+        Token::Comma => ",", Token::Dot => ".", Token::Minus => "-", Token::Plus => "+",
+        Token::Semicolon => ";", Token::Slash => "/", Token::Star => "*",
+        Token::Bang => "!", Token::BangEqual => "!=", Token::Equal => "=", Token::EqualEqual => "==",
+        Token::Greater => ">", Token::GreaterEqual => ">=", Token::Less => "<", Token::LessEqual => "<=",
+        Token::Identifier(n) => NAMES[*n as usize % NAMES.len()],
+        Token::String(n) => return format!("\"s{}\"", n),
+        Token::Number(n) => return format!("{}.{}", n / 10, n % 10),
+        Token::And => "and", Token::Class => "class", Token::Else => "else", Token::False => "false",
+        Token::Fun => "fun", Token::For => "for", Token::If => "if", Token::Nil => "nil", Token::Or => "or",
+        Token::Print => "print", Token::Return => "return", Token::Super => "super", Token::This => "this",
+        Token::True => "true", Token::Var => "var", Token::While => "while",
+        Token::Test => "test \"t\"",
+        Token::Newline => "\n",
+    };
+    text.to_string()
+}
+
+fuzz_target!(|tokens: Vec<Token>| {
+    let source: Vec<String> = tokens.iter().map(lexeme).collect();
+    let source = source.join(" ");
+
+    if analyze(&source).diagnostics.is_empty() {
+        let script = compile(&source).expect("analyze and compile disagree");
+        disassemble(&script);
+    }
+});
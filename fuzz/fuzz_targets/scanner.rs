@@ -0,0 +1,31 @@
+//! Scans arbitrary text, checking that every token points back into it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tapssp_project::{scanner::Scanner, token::TokenType};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let mut scanner = Scanner::new(source);
+    let mut line = 1;
+
+    // Every token but the last consumes at least one byte.
+    for _ in 0..=source.len() + 1 {
+        let token = scanner.scan_token();
+        assert!(token.line >= line, "lines went backwards");
+        line = token.line;
+
+        match token.token_type {
+            TokenType::Eof => return,
+            // Error tokens carry a message instead of their lexeme.
+            TokenType::Error => {},
+            _ => assert_eq!(&source[token.offset..token.offset + token.lexeme.len()], token.lexeme),
+        }
+    }
+
+    panic!("the scanner never reached the end of the source");
+});
@@ -0,0 +1,213 @@
+//! Runs programs generated from a small Lox grammar in a sandboxed VM with
+//! instruction, time and memory budgets. Any outcome but a panic or a
+//! crash is fine.
+
+#![no_main]
+
+use std::time::Duration;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tapssp_project::{compiler::{analyze, compile}, vm::VM};
+
+// Deeper nesting is cut off, keeping programs small enough to run quickly.
+const MAX_DEPTH: usize = 6;
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Name { A, B, C, F, G }
+
+impl Name {
+    fn text(self) -> &'static str {
+        match self {
+            Name::A => "a",
+            Name::B => "b",
+            Name::C => "c",
+            Name::F => "f",
+            Name::G => "g",
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Expr {
+    Number(i16),
+    String(u8),
+    Bool(bool),
+    Nil,
+    Variable(Name),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Assign(Name, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Native(Native, Vec<Expr>),
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum BinaryOp { Add, Subtract, Multiply, Divide, Equal, NotEqual, Less, Greater, And, Or }
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Native { Assert, AssertEq, AssertThrows }
+
+#[derive(Arbitrary, Debug)]
+enum Stmt {
+    Print(Expr),
+    Expression(Expr),
+    Var(Name, Option<Expr>),
+    Block(Vec<Stmt>),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    While(Expr, Vec<Stmt>),
+    For(Name, u8, Vec<Stmt>),
+    Fun(Name, Vec<Name>, Vec<Stmt>),
+    Return(Option<Expr>),
+}
+
+fn expr(e: &Expr, depth: usize, out: &mut String) {
+    if depth > MAX_DEPTH {
+        out.push_str("nil");
+        return;
+    }
+
+    match e {
+        Expr::Number(n) => out.push_str(&(*n as f64 / 4.0).to_string()),
+        Expr::String(n) => out.push_str(&format!("\"s{}\"", n)),
+        Expr::Bool(b) => out.push_str(&b.to_string()),
+        Expr::Nil => out.push_str("nil"),
+        Expr::Variable(name) => out.push_str(name.text()),
+        Expr::Not(operand) | Expr::Negate(operand) => {
+            out.push_str(if matches!(e, Expr::Not(_)) { "!(" } else { "-(" });
+            expr(operand, depth + 1, out);
+            out.push(')');
+        },
+        Expr::Binary(a, op, b) => {
+            let op = match op {
+                BinaryOp::Add => "+", BinaryOp::Subtract => "-", BinaryOp::Multiply => "*",
+                BinaryOp::Divide => "/", BinaryOp::Equal => "==", BinaryOp::NotEqual => "!=",
+                BinaryOp::Less => "<", BinaryOp::Greater => ">", BinaryOp::And => "and", BinaryOp::Or => "or",
+            };
+            out.push('(');
+            expr(a, depth + 1, out);
+            out.push_str(&format!(" {} ", op));
+            expr(b, depth + 1, out);
+            out.push(')');
+        },
+        Expr::Assign(name, e) => {
+            out.push_str(&format!("({} = ", name.text()));
+            expr(e, depth + 1, out);
+            out.push(')');
+        },
+        Expr::Call(callee, args) => {
+            out.push('(');
+            expr(callee, depth + 1, out);
+            out.push(')');
+            arguments(args, depth, out);
+        },
+        Expr::Native(native, args) => {
+            out.push_str(match native {
+                Native::Assert => "assert",
+                Native::AssertEq => "assert_eq",
+                Native::AssertThrows => "assert_throws",
+            });
+            arguments(args, depth, out);
+        },
+    }
+}
+
+fn arguments(args: &[Expr], depth: usize, out: &mut String) {
+    out.push('(');
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        expr(arg, depth + 1, out);
+    }
+    out.push(')');
+}
+
+fn block(stmts: &[Stmt], depth: usize, out: &mut String) {
+    out.push_str("{\n");
+    for s in stmts {
+        stmt(s, depth + 1, out);
+    }
+    out.push_str("}\n");
+}
+
+fn stmt(s: &Stmt, depth: usize, out: &mut String) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    match s {
+        Stmt::Print(e) | Stmt::Expression(e) => {
+            if matches!(s, Stmt::Print(_)) {
+                out.push_str("print ");
+            }
+            expr(e, depth, out);
+            out.push_str(";\n");
+        },
+        Stmt::Var(name, init) => {
+            out.push_str(&format!("var {}", name.text()));
+            if let Some(init) = init {
+                out.push_str(" = ");
+                expr(init, depth, out);
+            }
+            out.push_str(";\n");
+        },
+        Stmt::Block(stmts) => block(stmts, depth, out),
+        Stmt::If(condition, then, otherwise) => {
+            out.push_str("if (");
+            expr(condition, depth, out);
+            out.push_str(") ");
+            block(then, depth, out);
+            if let Some(otherwise) = otherwise {
+                out.push_str("else ");
+                block(otherwise, depth, out);
+            }
+        },
+        Stmt::While(condition, body) => {
+            out.push_str("while (");
+            expr(condition, depth, out);
+            out.push_str(") ");
+            block(body, depth, out);
+        },
+        Stmt::For(name, count, body) => {
+            let name = name.text();
+            out.push_str(&format!("for (var {0} = 0; {0} < {1}; {0} = {0} + 1) ", name, count));
+            block(body, depth, out);
+        },
+        Stmt::Fun(name, params, body) => {
+            let params: Vec<&str> = params.iter().map(|p| p.text()).collect();
+            out.push_str(&format!("fun {}({}) ", name.text(), params.join(", ")));
+            block(body, depth, out);
+        },
+        Stmt::Return(value) => {
+            out.push_str("return");
+            if let Some(value) = value {
+                out.push(' ');
+                expr(value, depth, out);
+            }
+            out.push_str(";\n");
+        },
+    }
+}
+
+fuzz_target!(|program: Vec<Stmt>| {
+    let mut source = String::new();
+    for s in &program {
+        stmt(s, 0, &mut source);
+    }
+
+    // Invalid programs are the parser target's business, and `compile`
+    // would print their errors.
+    if !analyze(&source).diagnostics.is_empty() {
+        return;
+    }
+    let script = compile(&source).unwrap();
+
+    let mut vm = VM::sandboxed();
+    vm.set_output(Box::new(std::io::sink()));
+    vm.set_instruction_budget(Some(100_000));
+    vm.set_time_limit(Some(Duration::from_secs(1)));
+    vm.set_memory_limit(Some(16 * 1024 * 1024));
+
+    let _ = vm.execute(script);
+});
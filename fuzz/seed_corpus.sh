@@ -0,0 +1,9 @@
+#!/bin/sh
+# Copies every .lox script in the repository into the scanner corpus,
+# named by its path. The parser and vm targets read it as a second corpus.
+set -e
+cd "$(dirname "$0")/.."
+mkdir -p fuzz/corpus/scanner
+find . -name '*.lox' -not -path './target/*' -not -path './fuzz/*' | sort | while read -r script; do
+    cp "$script" "fuzz/corpus/scanner/$(echo "${script#./}" | tr '/' '_')"
+done
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value as Json};
+use crate::{compiler::compile, error::LoxError, vm::VM};
+
+/// How long a benchmark took and how many instructions it ran.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+impl Measurement {
+    pub fn instructions_per_second(&self) -> f64 {
+        if self.time.is_zero() {
+            0.0
+        } else {
+            self.instructions as f64 / self.time.as_secs_f64()
+        }
+    }
+}
+
+/// Compiles `source` once and runs it `iterations` times, each time in a
+/// fresh VM with its output discarded, keeping the fastest run. Compiling
+/// and creating the VM are not timed.
+pub fn measure(source: &str, iterations: usize) -> Result<Measurement, LoxError> {
+    let script = compile(source)?;
+    let mut best: Option<Measurement> = None;
+
+    for _ in 0..iterations.max(1) {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+
+        let start = Instant::now();
+        vm.execute(script.clone())?;
+        let run = Measurement { instructions: vm.instruction_count(), time: start.elapsed() };
+
+        if best.is_none_or(|best| run.time < best.time) {
+            best = Some(run);
+        }
+    }
+
+    Ok(best.unwrap())
+}
+
+/// Measurements by benchmark name, saved between runs to compare against.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline(pub BTreeMap<String, Measurement>);
+
+impl Baseline {
+    pub fn to_json(&self) -> String {
+        let entries: serde_json::Map<String, Json> = self.0.iter().map(|(name, m)| {
+            (name.clone(), json!({ "instructions": m.instructions, "nanos": m.time.as_nanos() as u64 }))
+        }).collect();
+
+        serde_json::to_string_pretty(&entries).unwrap()
+    }
+
+    /// Reads a baseline written by `to_json`, skipping malformed entries.
+    pub fn from_json(text: &str) -> Result<Baseline, serde_json::Error> {
+        let json: Json = serde_json::from_str(text)?;
+        let mut baseline = Baseline::default();
+
+        for (name, entry) in json.as_object().into_iter().flatten() {
+            if let (Some(instructions), Some(nanos)) = (entry["instructions"].as_u64(), entry["nanos"].as_u64()) {
+                baseline.0.insert(name.clone(), Measurement { instructions, time: Duration::from_nanos(nanos) });
+            }
+        }
+
+        Ok(baseline)
+    }
+}
+
+/// A benchmark's current measurement next to the baseline's, if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub name: String,
+    pub current: Measurement,
+    pub baseline: Option<Measurement>,
+}
+
+impl Comparison {
+    /// The relative change in instructions per second, negative when slower.
+    pub fn change(&self) -> Option<f64> {
+        let before = self.baseline?.instructions_per_second();
+        if before == 0.0 {
+            None
+        } else {
+            Some(self.current.instructions_per_second() / before - 1.0)
+        }
+    }
+
+    /// Slower than the baseline by more than `threshold`, e.g. 0.1 for 10%.
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        self.change().is_some_and(|change| change < -threshold)
+    }
+}
+
+pub fn compare(current: &Baseline, baseline: &Baseline) -> Vec<Comparison> {
+    current.0.iter().map(|(name, m)| Comparison {
+        name: name.clone(),
+        current: *m,
+        baseline: baseline.0.get(name).copied(),
+    }).collect()
+}
+
+/// A table of the comparisons, flagging regressions beyond `threshold` and
+/// benchmarks whose instruction count changed.
+pub fn report(comparisons: &[Comparison], threshold: f64) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "{:<16} {:>14} {:>12} {:>14} {:>9}", "benchmark", "instructions", "time", "instr/s", "change");
+
+    for c in comparisons {
+        let change = c.change().map_or("new".to_string(), |change| format!("{:+.1}%", change * 100.0));
+        let _ = write!(
+            report, "{:<16} {:>14} {:>12} {:>12.1}M/s {:>9}",
+            c.name, c.current.instructions, format!("{:.2?}", c.current.time),
+            c.current.instructions_per_second() / 1e6, change
+        );
+
+        if c.is_regression(threshold) {
+            report.push_str("  REGRESSION");
+        }
+        if let Some(baseline) = c.baseline && baseline.instructions != c.current.instructions {
+            let _ = write!(report, "  (was {} instructions)", baseline.instructions);
+        }
+        report.push('\n');
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(instructions: u64, millis: u64) -> Measurement {
+        Measurement { instructions, time: Duration::from_millis(millis) }
+    }
+
+    #[test]
+    fn test_measure() {
+        let source = "var sum = 0;\nfor (var i = 0; i < 100; i = i + 1) sum = sum + i;\nprint sum;";
+        let m = measure(source, 3).unwrap();
+
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.interpret(source).unwrap();
+        assert_eq!(m.instructions, vm.instruction_count());
+
+        assert!(matches!(measure("print -nil;", 1), Err(LoxError::RuntimeError(_))));
+    }
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let mut baseline = Baseline::default();
+        baseline.0.insert("fib".to_string(), measurement(1000, 5));
+        baseline.0.insert("loops".to_string(), measurement(2000, 7));
+
+        assert_eq!(Baseline::from_json(&baseline.to_json()).unwrap(), baseline);
+        assert_eq!(Baseline::from_json("{\"bad\": {\"nanos\": 1}}").unwrap(), Baseline::default());
+        assert!(Baseline::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_regressions() {
+        let mut before = Baseline::default();
+        before.0.insert("fib".to_string(), measurement(1000, 10));
+        before.0.insert("loops".to_string(), measurement(1000, 10));
+
+        let mut after = Baseline::default();
+        after.0.insert("fib".to_string(), measurement(1000, 20));
+        after.0.insert("loops".to_string(), measurement(1000, 9));
+        after.0.insert("strings".to_string(), measurement(1000, 9));
+
+        let comparisons = compare(&after, &before);
+        assert_eq!(comparisons[0].change(), Some(-0.5));
+        assert!(comparisons[0].is_regression(0.1));
+        assert!(!comparisons[1].is_regression(0.1));
+        assert_eq!(comparisons[2].change(), None);
+
+        let report = report(&comparisons, 0.1);
+        assert!(report.lines().nth(1).unwrap().ends_with("-50.0%  REGRESSION"));
+        assert!(report.lines().nth(3).unwrap().ends_with("new"));
+    }
+
+    #[test]
+    fn test_instruction_changes_are_flagged() {
+        let mut before = Baseline::default();
+        before.0.insert("fib".to_string(), measurement(1000, 10));
+        let mut after = Baseline::default();
+        after.0.insert("fib".to_string(), measurement(900, 9));
+
+        assert!(report(&compare(&after, &before), 0.1).contains("(was 1000 instructions)"));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{objects::LoxString, value::Value, op::OpCode};
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Block {
@@ -8,6 +8,12 @@ pub struct Block {
     pub lines: Vec<u16>,
 }
 
+impl Default for Block {
+    fn default() -> Self {
+        Block::new()
+    }
+}
+
 impl Block {
     pub fn new() -> Block {
         Block {
@@ -32,7 +38,7 @@ impl Block {
         &self.constants[index as usize]
     }
 
-    pub fn read_string(&self, index: u8) -> Rc<LoxString> {
+    pub fn read_string(&self, index: u8) -> Arc<LoxString> {
         if let Value::String(s) = self.read_constant(index) {
             s.clone()
         } else {
@@ -1,12 +1,78 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::{
-    block::{Block}, scanner::{Scanner}, token::{Token, TokenType}, value::Value, error::LoxError, op::OpCode, objects::{LoxString, Function}
+    scanner::{Scanner}, token::{Token, TokenType}, value::Value, error::LoxError, op::OpCode, objects::{LoxString, Function, LocalInfo}
 };
 
-pub fn compile(source: &str) -> Result<Rc<Function>, LoxError> {
+pub fn compile(source: &str) -> Result<Arc<Function>, LoxError> {
     let mut parser = Parser::new(source);
     let function = parser.compile()?;
-    Ok(Rc::new(*function))
+    Ok(Arc::new(*function))
+}
+
+/// A compile error, located by the byte range of the offending token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+    pub offset: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+}
+
+/// A declared name. Locals are only visible in `scope`, a byte range of
+/// the source; globals have no scope and are visible everywhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub offset: usize,
+    pub scope: Option<(usize, usize)>,
+    pub parameters: Vec<String>,
+}
+
+/// A use of a name, `symbol` indexing the declaration it resolves to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub offset: usize,
+    pub length: usize,
+    pub symbol: Option<usize>,
+}
+
+/// What compiling a source file revealed, for editor tooling.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+/// Compiles `source` without reporting errors, collecting its diagnostics,
+/// declarations and the references between them.
+pub fn analyze(source: &str) -> Analysis {
+    let mut parser = Parser::new(source);
+    parser.report_errors = false;
+    let _ = parser.compile();
+
+    let mut analysis = Analysis {
+        diagnostics: parser.diagnostics,
+        symbols: parser.symbols,
+        references: parser.references,
+    };
+
+    // Globals can be used before they are declared, e.g. inside a function
+    // body, so they are resolved by name once the whole file is known.
+    for reference in analysis.references.iter_mut().filter(|r| r.symbol.is_none()) {
+        let name = &source[reference.offset..reference.offset + reference.length];
+        reference.symbol = analysis.symbols.iter().position(|s| s.scope.is_none() && s.name == name);
+    }
+
+    analysis
 }
 
 #[derive(PartialEq, PartialOrd, Clone, Copy)]
@@ -59,11 +125,13 @@ impl<'a> ParseRule<'a> {
 struct Local<'a> {
     token: Token<'a>,
     depth: i32,
+    info: Option<usize>,
+    symbol: Option<usize>,
 }
 
 impl<'a> Local<'a> {
     fn new(token: Token<'a>, depth: i32) -> Self {
-        Local { token, depth }
+        Local { token, depth, info: None, symbol: None }
     }
 }
 
@@ -84,7 +152,7 @@ struct Compiler<'a> {
 impl<'a> Compiler<'a> {
     const MAX_LOCALS: usize = u8::MAX as usize + 1;
 
-    pub fn new(function_name: Rc<LoxString>, function_type: FunctionType) -> Self {
+    pub fn new(function_name: Arc<LoxString>, function_type: FunctionType) -> Self {
         let mut compiler = Compiler {
             enclosing: None,
             function: Some(Function::new(function_name)),
@@ -134,6 +202,10 @@ struct Parser<'a> {
     resolve_errors: Vec<&'static str>,
     had_error: bool,
     panic_mode: bool,
+    report_errors: bool,
+    diagnostics: Vec<Diagnostic>,
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
 }
 
 impl<'a> Parser<'a> {
@@ -148,7 +220,11 @@ impl<'a> Parser<'a> {
             rules: Vec::with_capacity(40),
             resolve_errors: Vec::with_capacity(16),
             had_error: false,
-            panic_mode: false
+            panic_mode: false,
+            report_errors: true,
+            diagnostics: Vec::new(),
+            symbols: Vec::new(),
+            references: Vec::new(),
         };
 
         parser.add_rule(Some(Parser::grouping), Some(Parser::call), Precedence::Call); // LeftParen
@@ -244,6 +320,10 @@ impl<'a> Parser<'a> {
             self.fun_declaration();
         } else if self.matches(TokenType::Var) {
             self.var_declaration();
+        } else if self.check(TokenType::Identifier) && self.current.lexeme == "test"
+            && self.scanner.peek_token().token_type == TokenType::String {
+            self.advance();
+            self.test_declaration();
         } else {
             self.statement();
         }
@@ -255,13 +335,47 @@ impl<'a> Parser<'a> {
 
     fn fun_declaration(&mut self) {
         let global = self.parse_variable("Expected function name.");
+        let symbol = self.add_symbol(SymbolKind::Function);
         self.mark_initialized();
         self.function(FunctionType::Function);
         self.define_variable(global);
+
+        if let Some(symbol) = symbol {
+            let parameters = self.symbols[symbol + 1..].iter()
+                .take_while(|s| s.kind == SymbolKind::Parameter)
+                .map(|s| s.name.clone())
+                .collect();
+            self.symbols[symbol].parameters = parameters;
+        }
+    }
+
+    /// `test "name" { ... }` compiles the body into a function kept in the
+    /// script's `tests`; it only runs when the script's tests are run.
+    fn test_declaration(&mut self) {
+        self.advance();
+        let token = self.previous;
+        let name = &token.lexeme[1..token.lexeme.len() - 1];
+
+        self.compiler_push(name, FunctionType::Function);
+        self.begin_scope();
+        self.match_token(TokenType::LeftBrace, "Expected '{' before test body.");
+        self.block();
+
+        let test = self.compiler_pop();
+
+        // The body parsed fine, so neither error needs to resynchronize.
+        if self.compiler.function_type != FunctionType::Script || self.compiler.scope_depth > 0 {
+            self.error(token, "Tests must be declared at top level.");
+            self.panic_mode = false;
+        } else if self.compiler.function.as_ref().unwrap().tests.iter().any(|t| t.name.value == name) {
+            self.error(token, "Already a test with this name.");
+            self.panic_mode = false;
+        }
+        self.compiler.function.as_mut().unwrap().tests.push(Arc::new(*test));
     }
 
     fn function(&mut self, function_type: FunctionType) {
-        self.compiler_push(function_type);
+        self.compiler_push(self.previous.lexeme, function_type);
         self.begin_scope();
 
         self.match_token(TokenType::LeftParen, "Expected '(' after function name.");
@@ -274,6 +388,7 @@ impl<'a> Parser<'a> {
                 }
 
                 let param_constant = self.parse_variable("Expected parameter name.");
+                self.add_symbol(SymbolKind::Parameter);
                 self.define_variable(param_constant);
 
                 if !self.matches(TokenType::Comma) {
@@ -289,12 +404,11 @@ impl<'a> Parser<'a> {
 
         let function = self.compiler_pop();
 
-        let index = self.make_constant(Value::Function(Rc::new(*function)));
+        let index = self.make_constant(Value::Function(Arc::new(*function)));
         self.emit_instr(OpCode::Constant(index));
     }
 
-    fn compiler_push(&mut self, function_type: FunctionType) {
-        let name = self.previous.lexeme;
+    fn compiler_push(&mut self, name: &str, function_type: FunctionType) {
         let compiler = Compiler::new(LoxString::from_string(name), function_type);
         let prev_compiler = std::mem::replace(&mut self.compiler, compiler);
         self.compiler.enclosing = Some(Box::new(prev_compiler));
@@ -303,6 +417,10 @@ impl<'a> Parser<'a> {
     fn compiler_pop(&mut self) -> Box<Function> {
         self.emit_return();
 
+        while let Some(local) = self.compiler.locals.pop() {
+            self.close_local(local);
+        }
+
         match self.compiler.enclosing.take() {
             Some(enclosing) => {
                 let compiler = std::mem::replace(&mut self.compiler, *enclosing);
@@ -314,6 +432,7 @@ impl<'a> Parser<'a> {
 
     fn var_declaration(&mut self) {
         let global = self.parse_variable("Expected variable name.");
+        self.add_symbol(SymbolKind::Variable);
 
         if self.matches(TokenType::Equal) {
             self.expression();
@@ -360,6 +479,32 @@ impl<'a> Parser<'a> {
         self.identifier_constant(self.previous)
     }
 
+    /// Records the name just parsed by `parse_variable` as a declaration.
+    fn add_symbol(&mut self, kind: SymbolKind) -> Option<usize> {
+        let name = self.previous;
+        if name.token_type != TokenType::Identifier {
+            return None;
+        }
+
+        let index = self.symbols.len();
+        let mut scope = None;
+
+        if self.compiler.scope_depth > 0 && let Some(local) = self.compiler.locals.last_mut() && local.symbol.is_none() {
+            local.symbol = Some(index);
+            scope = Some((name.offset, usize::MAX));
+        }
+
+        self.symbols.push(Symbol {
+            name: name.lexeme.to_string(),
+            kind,
+            offset: name.offset,
+            scope,
+            parameters: Vec::new(),
+        });
+
+        Some(index)
+    }
+
     fn identifier_constant(&mut self, name: Token) -> u8 {
         self.make_constant(Value::String(LoxString::from_string(name.lexeme)))
     }
@@ -379,7 +524,14 @@ impl<'a> Parser<'a> {
     }
 
     fn named_variable(&mut self, name: Token<'a>, is_assign: bool) {
-        let (get_op, set_op) = if let Some(arg) = self.resolve_local(&name) {
+        let local = self.resolve_local(&name);
+        self.references.push(Reference {
+            offset: name.offset,
+            length: name.lexeme.len(),
+            symbol: local.and_then(|slot| self.compiler.locals[slot as usize].symbol),
+        });
+
+        let (get_op, set_op) = if let Some(arg) = local {
             (OpCode::GetLocal(arg), OpCode::SetLocal(arg))
         } else {
             let global = self.identifier_constant(name);
@@ -396,11 +548,36 @@ impl<'a> Parser<'a> {
 
     fn mark_initialized(&mut self) {
         if self.compiler.scope_depth > 0 {
+            let function = self.compiler.function.as_mut().unwrap();
+            let slot = self.compiler.locals.len() - 1;
             let last = self.compiler.locals.last_mut().unwrap();
+
+            if last.info.is_none() {
+                function.locals.push(LocalInfo {
+                    name: last.token.lexeme.to_string(),
+                    slot: slot as u8,
+                    start: function.block.code.len(),
+                    end: usize::MAX,
+                });
+                last.info = Some(function.locals.len() - 1);
+            }
+
             last.depth = self.compiler.scope_depth;
         }
     }
 
+    fn close_local(&mut self, local: Local<'a>) {
+        if let Some(info) = local.info {
+            let function = self.compiler.function.as_mut().unwrap();
+            function.locals[info].end = function.block.code.len();
+        }
+
+        if let Some(symbol) = local.symbol && let Some((start, _)) = self.symbols[symbol].scope {
+            let end = self.previous.offset + self.previous.lexeme.len();
+            self.symbols[symbol].scope = Some((start, end));
+        }
+    }
+
     fn define_variable(&mut self, global: u8) {
         if self.compiler.scope_depth > 0 {
             self.mark_initialized();
@@ -426,8 +603,9 @@ impl<'a> Parser<'a> {
         self.compiler.scope_depth -= 1;
 
         while !self.compiler.locals.is_empty() && self.compiler.locals[self.compiler.locals.len() - 1].depth > self.compiler.scope_depth {
+            let local = self.compiler.locals.pop().unwrap();
+            self.close_local(local);
             self.emit_instr(OpCode::Pop);
-            self.compiler.locals.pop();
         }
     }
 
@@ -721,7 +899,6 @@ impl<'a> Parser<'a> {
             },
             _ => {
                 self.error_previous("Can only patch jump instructions.");
-                return;
             }
         }
     }
@@ -790,6 +967,19 @@ impl<'a> Parser<'a> {
         self.had_error = true;
         self.panic_mode = true;
 
+        // Error tokens carry the scanner's message instead of a lexeme, and
+        // point at the character that caused the error.
+        let length = match token.token_type {
+            TokenType::Eof => 0,
+            TokenType::Error => self.scanner.source()[token.offset..].chars().next().map_or(0, char::len_utf8),
+            _ => token.lexeme.len(),
+        };
+        self.diagnostics.push(Diagnostic { message: msg.to_string(), line: token.line, offset: token.offset, length });
+
+        if !self.report_errors {
+            return;
+        }
+
         eprint!("[line {}] Error at ", token.line);
 
         match token.token_type {
@@ -797,9 +987,6 @@ impl<'a> Parser<'a> {
             _ => eprintln!("{}", token.lexeme),
         }
         eprintln!(": {}", msg);
-
-        self.had_error = true;
-        self.panic_mode = true;
     }
 
     fn sync(&mut self) {
@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use crate::{instrument::{self, Instrument}, objects::Function, op::OpCode, value::Value};
+
+/// How often each side of a `JumpIfFalse` was taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub line: u16,
+    pub executed: bool,
+    /// The condition was truthy and execution fell through.
+    pub fallthrough: u64,
+    /// The condition was falsey and the jump was taken.
+    pub jumped: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCoverage {
+    pub name: String,
+    pub line: u16,
+    pub calls: u64,
+}
+
+/// Coverage of one source file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileCoverage {
+    pub path: String,
+    /// Hits of every line holding code.
+    pub lines: BTreeMap<u16, u64>,
+    pub branches: Vec<BranchCoverage>,
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl FileCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    /// Branch sides taken at least once, out of two per branch.
+    pub fn branches_hit(&self) -> usize {
+        self.branches.iter().map(|b| (b.fallthrough > 0) as usize + (b.jumped > 0) as usize).sum()
+    }
+
+    pub fn functions_hit(&self) -> usize {
+        self.functions.iter().filter(|f| f.calls > 0).count()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: lines {}/{}, branches {}/{}, functions {}/{}",
+            self.path, self.lines_hit(), self.lines.len(), self.branches_hit(), self.branches.len() * 2,
+            self.functions_hit(), self.functions.len()
+        )
+    }
+
+    /// One lcov record for the file.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+        let _ = writeln!(lcov, "SF:{}", self.path);
+
+        for f in &self.functions {
+            let _ = writeln!(lcov, "FN:{},{}", f.line, f.name);
+        }
+        for f in &self.functions {
+            let _ = writeln!(lcov, "FNDA:{},{}", f.calls, f.name);
+        }
+        let _ = writeln!(lcov, "FNF:{}\nFNH:{}", self.functions.len(), self.functions_hit());
+
+        for (block, b) in self.branches.iter().enumerate() {
+            for (branch, taken) in [b.fallthrough, b.jumped].into_iter().enumerate() {
+                let taken = if b.executed { taken.to_string() } else { "-".to_string() };
+                let _ = writeln!(lcov, "BRDA:{},{},{},{}", b.line, block, branch, taken);
+            }
+        }
+        let _ = writeln!(lcov, "BRF:{}\nBRH:{}", self.branches.len() * 2, self.branches_hit());
+
+        for (line, hits) in &self.lines {
+            let _ = writeln!(lcov, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(lcov, "LF:{}\nLH:{}", self.lines.len(), self.lines_hit());
+
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+struct Tracked {
+    file: usize,
+    function: Arc<Function>,
+    calls: u64,
+    hits: Vec<u64>,
+    fallthrough: HashMap<usize, u64>,
+    jumped: HashMap<usize, u64>,
+}
+
+#[derive(Default)]
+struct State {
+    paths: Vec<String>,
+    functions: Vec<Tracked>,
+    by_address: HashMap<usize, usize>,
+    // A `JumpIfFalse` whose outcome shows in the next instruction.
+    pending: Option<(usize, usize)>,
+}
+
+impl State {
+    fn track(&mut self, file: usize, function: &Arc<Function>) {
+        let address = Arc::as_ptr(function) as usize;
+        if self.by_address.contains_key(&address) {
+            return;
+        }
+
+        self.by_address.insert(address, self.functions.len());
+        self.functions.push(Tracked {
+            file,
+            function: function.clone(),
+            calls: 0,
+            hits: vec![0; function.block.code.len()],
+            fallthrough: HashMap::new(),
+            jumped: HashMap::new(),
+        });
+
+        for constant in function.block.constants.iter() {
+            if let Value::Function(f) = constant {
+                self.track(file, f);
+            }
+        }
+    }
+
+    fn lookup(&self, function: &Function) -> Option<usize> {
+        self.by_address.get(&(function as *const Function as usize)).copied()
+    }
+}
+
+/// Records which instructions and branches of registered scripts run.
+///
+/// Scripts must be added with `add_script` before they run; functions
+/// compiled any other way are ignored. Clones share the same results.
+#[derive(Clone, Default)]
+pub struct Coverage(Arc<Mutex<State>>);
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Tracks `script` and every function declared in it as the file `path`.
+    pub fn add_script(&self, path: &str, script: &Arc<Function>) {
+        let mut state = self.0.lock().unwrap();
+
+        let file = match state.paths.iter().position(|p| p == path) {
+            Some(file) => file,
+            None => {
+                state.paths.push(path.to_string());
+                state.paths.len() - 1
+            },
+        };
+
+        state.track(file, script);
+    }
+
+    pub fn files(&self) -> Vec<FileCoverage> {
+        let state = self.0.lock().unwrap();
+
+        let mut files: Vec<FileCoverage> = state.paths.iter()
+            .map(|path| FileCoverage { path: path.clone(), ..FileCoverage::default() })
+            .collect();
+
+        for tracked in &state.functions {
+            let file = &mut files[tracked.file];
+            let block = &tracked.function.block;
+
+            // The implicit `nil` return at the end of every function sits on
+            // the closing line, which only counts as code if it ever ran.
+            let implicit_return = block.code.len().saturating_sub(2);
+            for (offset, hits) in tracked.hits.iter().enumerate() {
+                if offset >= implicit_return && *hits == 0 {
+                    continue;
+                }
+
+                let line = file.lines.entry(block.lines[offset]).or_default();
+                *line = (*line).max(*hits);
+            }
+
+            for (offset, op) in block.code.iter().enumerate() {
+                if let OpCode::JumpIfFalse(_) = op {
+                    file.branches.push(BranchCoverage {
+                        line: block.lines[offset],
+                        executed: tracked.hits[offset] > 0,
+                        fallthrough: tracked.fallthrough.get(&offset).copied().unwrap_or(0),
+                        jumped: tracked.jumped.get(&offset).copied().unwrap_or(0),
+                    });
+                }
+            }
+
+            if tracked.function.name.value != "script" {
+                file.functions.push(FunctionCoverage {
+                    name: instrument::function_name(&tracked.function),
+                    line: block.lines.iter().copied().min().unwrap_or(0),
+                    calls: tracked.calls,
+                });
+            }
+        }
+
+        for file in files.iter_mut() {
+            file.branches.sort_by_key(|b| b.line);
+            file.functions.sort_by_key(|f| f.line);
+        }
+
+        files
+    }
+
+    /// An lcov tracefile covering every registered file.
+    pub fn lcov(&self) -> String {
+        self.files().iter().map(FileCoverage::lcov).collect()
+    }
+}
+
+impl Instrument for Coverage {
+    fn on_call(&mut self, function: &Function, _depth: usize) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(index) = state.lookup(function) {
+            state.functions[index].calls += 1;
+        }
+    }
+
+    fn on_instruction(&mut self, function: &Function, offset: usize, _line: u16, _depth: usize, _stack: &[Value]) {
+        let mut state = self.0.lock().unwrap();
+
+        if let Some((index, branch)) = state.pending.take() {
+            let tracked = &mut state.functions[index];
+            let outcome = if offset == branch + 1 { &mut tracked.fallthrough } else { &mut tracked.jumped };
+            *outcome.entry(branch).or_default() += 1;
+        }
+
+        let Some(index) = state.lookup(function) else {
+            return;
+        };
+
+        state.functions[index].hits[offset] += 1;
+        if let OpCode::JumpIfFalse(_) = function.block.code[offset] {
+            state.pending = Some((index, offset));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, vm::VM};
+
+    const PROGRAM: &str = "\
+fun sign(n) {
+    if (n < 0) {
+        return -1;
+    }
+    return 1;
+}
+fun unused() {
+    return 0;
+}
+var i = 0;
+while (i < 3) {
+    i = i + 1;
+}
+print sign(5);
+";
+
+    fn cover(sources: &[(&str, &str)]) -> Coverage {
+        let coverage = Coverage::new();
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.add_instrument(Box::new(coverage.clone()));
+
+        for (path, source) in sources {
+            let script = compile(source).unwrap();
+            coverage.add_script(path, &script);
+            vm.execute(script).unwrap();
+        }
+
+        coverage
+    }
+
+    #[test]
+    fn test_line_coverage() {
+        let files = cover(&[("sign.lox", PROGRAM)]).files();
+        let lines: Vec<(u16, u64)> = files[0].lines.iter().map(|(l, h)| (*l, *h)).collect();
+
+        assert_eq!(lines, vec![
+            (2, 1), (3, 0), (4, 1), (5, 1), (6, 1), (8, 0), (9, 1), (10, 1), (11, 4), (12, 3), (13, 3), (14, 1), (15, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_branch_coverage() {
+        let files = cover(&[("sign.lox", PROGRAM)]).files();
+
+        assert_eq!(files[0].branches, vec![
+            BranchCoverage { line: 2, executed: true, fallthrough: 0, jumped: 1 },
+            BranchCoverage { line: 11, executed: true, fallthrough: 3, jumped: 1 },
+        ]);
+        assert_eq!(files[0].branches_hit(), 3);
+    }
+
+    #[test]
+    fn test_function_coverage() {
+        let files = cover(&[("sign.lox", PROGRAM)]).files();
+
+        assert_eq!(files[0].functions, vec![
+            FunctionCoverage { name: "sign".to_string(), line: 2, calls: 1 },
+            FunctionCoverage { name: "unused".to_string(), line: 8, calls: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_unexecuted_branch() {
+        let files = cover(&[("a.lox", "fun f(x) { if (x) print 1; }\nvar y = 1;")]).files();
+
+        assert!(!files[0].branches[0].executed);
+        assert!(files[0].lcov().contains("BRDA:1,0,0,-\nBRDA:1,0,1,-\n"));
+    }
+
+    #[test]
+    fn test_lcov() {
+        let coverage = cover(&[("sign.lox", PROGRAM), ("other.lox", "print 1;")]);
+        let lcov = coverage.lcov();
+
+        assert_eq!(lcov.matches("end_of_record").count(), 2);
+        assert!(lcov.starts_with("SF:sign.lox\nFN:2,sign\nFN:8,unused\nFNDA:1,sign\nFNDA:0,unused\nFNF:2\nFNH:1\n"));
+        assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\nBRDA:11,1,0,3\nBRDA:11,1,1,1\nBRF:4\nBRH:3\n"));
+        assert!(lcov.contains("DA:3,0\n"));
+        assert!(lcov.contains("LF:13\nLH:11\nend_of_record\nSF:other.lox\n"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let files = cover(&[("sign.lox", PROGRAM)]).files();
+        assert_eq!(files[0].summary(), "sign.lox: lines 11/13, branches 3/4, functions 1/2");
+    }
+}
@@ -0,0 +1,545 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use serde_json::{json, Value as Json};
+use crate::{
+    compiler::compile, debugger::{self, DebugHook, PauseReason, Resume}, objects::Function, protocol, vm::VM
+};
+
+// Lox programs are single threaded, so there is only ever one thread to report.
+const THREAD_ID: i64 = 1;
+const GLOBALS_REFERENCE: i64 = 1;
+// The locals of frame index `i` have reference `LOCALS_REFERENCE + i`.
+const LOCALS_REFERENCE: i64 = GLOBALS_REFERENCE + 1;
+
+/// Sends protocol messages, numbering them in the order they are sent.
+struct Client<W> {
+    output: W,
+    seq: i64,
+}
+
+type SharedClient<W> = Arc<Mutex<Client<W>>>;
+
+impl<W: Write> Client<W> {
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let _ = protocol::write_message(&mut self.output, &message);
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// Forwards the output of `print` statements to the client as output events.
+struct OutputEvents<W>(SharedClient<W>);
+
+impl<W: Write> Write for OutputEvents<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = String::from_utf8_lossy(buf);
+        self.0.lock().unwrap().event("output", json!({ "category": "stdout", "output": output }));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Program {
+    path: String,
+    script: Arc<Function>,
+    stop_on_entry: bool,
+}
+
+/// State shared by the server loop and the hook that serves requests while
+/// the program is paused.
+struct Session<R, W> {
+    input: R,
+    client: SharedClient<W>,
+    program: Option<Program>,
+    disconnected: bool,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    fn read_request(&mut self) -> io::Result<Option<Json>> {
+        loop {
+            match protocol::read_message(&mut self.input)? {
+                Some(message) if message["type"] == "request" => return Ok(Some(message)),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn respond(&self, request: &Json, body: Json) {
+        self.client.lock().unwrap().respond(request, body);
+    }
+
+    fn fail(&self, request: &Json, message: &str) {
+        self.client.lock().unwrap().fail(request, message);
+    }
+
+    /// Serves a request, returning how to resume if it ends a pause.
+    fn handle(&mut self, vm: &mut VM, request: &Json) -> Option<Resume> {
+        let arguments = &request["arguments"];
+
+        let resume = match request["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                self.respond(request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }));
+                self.client.lock().unwrap().event("initialized", json!({}));
+                return None;
+            },
+            "launch" => {
+                self.launch(request);
+                return None;
+            },
+            "setBreakpoints" => {
+                self.set_breakpoints(vm, request);
+                return None;
+            },
+            "threads" => {
+                self.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }));
+                return None;
+            },
+            "stackTrace" => {
+                self.stack_trace(vm, request);
+                return None;
+            },
+            "scopes" => {
+                let index = self.frame_index(vm, request)?;
+
+                self.respond(request, json!({ "scopes": [
+                    { "name": "Locals", "variablesReference": LOCALS_REFERENCE + index as i64, "expensive": false },
+                    { "name": "Globals", "variablesReference": GLOBALS_REFERENCE, "expensive": false },
+                ]}));
+                return None;
+            },
+            "variables" => {
+                let variables = match arguments["variablesReference"].as_i64().unwrap_or(0) {
+                    GLOBALS_REFERENCE => vm.globals(),
+                    reference if reference >= LOCALS_REFERENCE => vm.locals((reference - LOCALS_REFERENCE) as usize),
+                    _ => Vec::new(),
+                };
+
+                let variables: Vec<Json> = variables.iter().map(|(name, value)| json!({
+                    "name": name,
+                    "value": debugger::describe(value),
+                    "variablesReference": 0,
+                })).collect();
+                self.respond(request, json!({ "variables": variables }));
+                return None;
+            },
+            "evaluate" => {
+                self.evaluate(vm, request);
+                return None;
+            },
+            "continue" => Resume::Continue,
+            "next" => Resume::StepOver,
+            "stepIn" => Resume::StepIn,
+            "stepOut" => Resume::StepOut,
+            "disconnect" => {
+                self.disconnected = true;
+                Resume::Stop
+            },
+            command => {
+                self.fail(request, &format!("Unsupported request '{}'", command));
+                return None;
+            },
+        };
+
+        if vm.frames().is_empty() && resume != Resume::Stop {
+            self.fail(request, "The program is not paused");
+            return None;
+        }
+
+        let body = if resume == Resume::Continue { json!({ "allThreadsContinued": true }) } else { json!({}) };
+        self.respond(request, body);
+        Some(resume)
+    }
+
+    fn launch(&mut self, request: &Json) {
+        let arguments = &request["arguments"];
+        let Some(path) = arguments["program"].as_str() else {
+            return self.fail(request, "Missing 'program' argument");
+        };
+
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return self.fail(request, &format!("Could not read '{}': {}", path, e)),
+        };
+
+        match compile(&source) {
+            Ok(script) => {
+                let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                self.program = Some(Program { path: path.to_string(), script, stop_on_entry });
+                self.respond(request, json!({}));
+            },
+            Err(e) => self.fail(request, &e.to_string()),
+        }
+    }
+
+    fn set_breakpoints(&mut self, vm: &mut VM, request: &Json) {
+        // Only the launched program is debugged, so the breakpoints of any
+        // source replace the whole set.
+        for line in vm.breakpoints() {
+            vm.remove_breakpoint(line);
+        }
+
+        let requested = request["arguments"]["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Json> = requested.iter().map(|breakpoint| {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as u16;
+            let resolved = self.program.as_ref().and_then(|p| debugger::resolve_line(&p.script, line));
+
+            match resolved {
+                Some(line) => {
+                    vm.add_breakpoint(line);
+                    json!({ "verified": true, "line": line })
+                },
+                None => json!({ "verified": false, "line": line }),
+            }
+        }).collect();
+
+        self.respond(request, json!({ "breakpoints": breakpoints }));
+    }
+
+    fn stack_trace(&self, vm: &VM, request: &Json) {
+        let source = self.program.as_ref().map(|p| json!({ "name": p.path, "path": p.path }));
+
+        let frames: Vec<Json> = vm.frames().iter().map(|frame| json!({
+            "id": frame.index + 1,
+            "name": if frame.index == 0 { "<script>".to_string() } else { frame.function.clone() },
+            "line": frame.line,
+            "column": 1,
+            "source": source,
+        })).collect();
+
+        self.respond(request, json!({ "totalFrames": frames.len(), "stackFrames": frames }));
+    }
+
+    /// The index of the frame with the request's `frameId`, or of the
+    /// innermost frame if it has none. Fails the request if there is no
+    /// such frame.
+    fn frame_index(&self, vm: &VM, request: &Json) -> Option<usize> {
+        let Some(id) = request["arguments"]["frameId"].as_u64() else {
+            return Some(vm.frames().first().map_or(0, |frame| frame.index));
+        };
+
+        // Frame ids are the frame index plus one.
+        let index = usize::try_from(id).ok().and_then(|id| id.checked_sub(1));
+        let index = index.filter(|index| vm.frames().iter().any(|frame| frame.index == *index));
+        if index.is_none() {
+            self.fail(request, &format!("Unknown frame {}", id));
+        }
+        index
+    }
+
+    fn evaluate(&self, vm: &mut VM, request: &Json) {
+        let expression = request["arguments"]["expression"].as_str().unwrap_or("");
+        let Some(frame) = self.frame_index(vm, request) else {
+            return;
+        };
+
+        match vm.evaluate(frame, expression) {
+            Ok(value) => self.respond(request, json!({ "result": debugger::describe(&value), "variablesReference": 0 })),
+            Err(e) => self.fail(request, &e.to_string()),
+        }
+    }
+}
+
+struct DapHook<R, W> {
+    session: Arc<Mutex<Session<R, W>>>,
+}
+
+impl<R: BufRead + Send, W: Write + Send> DebugHook for DapHook<R, W> {
+    fn on_pause(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+        let mut session = self.session.lock().unwrap();
+
+        let reason = match reason {
+            PauseReason::Entry => "entry",
+            PauseReason::Breakpoint => "breakpoint",
+            PauseReason::Step => "step",
+        };
+        session.client.lock().unwrap().event("stopped", json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }));
+
+        loop {
+            let request = match session.read_request() {
+                Ok(Some(request)) => request,
+                _ => {
+                    session.disconnected = true;
+                    return Resume::Stop;
+                },
+            };
+
+            if let Some(resume) = session.handle(vm, &request) {
+                return resume;
+            }
+        }
+    }
+}
+
+/// A Debug Adapter Protocol server debugging one program over a byte stream.
+pub struct DapServer<R, W> {
+    session: Arc<Mutex<Session<R, W>>>,
+}
+
+impl<R: BufRead + Send + 'static, W: Write + Send + 'static> DapServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        let session = Session {
+            input,
+            client: Arc::new(Mutex::new(Client { output, seq: 0 })),
+            program: None,
+            disconnected: false,
+        };
+
+        DapServer { session: Arc::new(Mutex::new(session)) }
+    }
+
+    /// Serves requests until the client disconnects or closes the stream.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(OutputEvents(self.session.lock().unwrap().client.clone())));
+        // Stdin carries the protocol, so the program reads an empty input.
+        vm.set_input(Box::new(io::empty()));
+
+        loop {
+            let mut session = self.session.lock().unwrap();
+            let Some(request) = session.read_request()? else {
+                return Ok(());
+            };
+
+            match request["command"].as_str().unwrap_or("") {
+                "configurationDone" => {
+                    if session.program.is_none() {
+                        session.fail(&request, "No program was launched");
+                        continue;
+                    }
+
+                    session.respond(&request, json!({}));
+                    drop(session);
+
+                    if !self.execute(&mut vm) {
+                        return Ok(());
+                    }
+                },
+                "disconnect" => {
+                    session.respond(&request, json!({}));
+                    return Ok(());
+                },
+                _ => {
+                    session.handle(&mut vm, &request);
+                },
+            }
+        }
+    }
+
+    /// Runs the launched program, returning false if the client went away.
+    fn execute(&mut self, vm: &mut VM) -> bool {
+        let (script, stop_on_entry) = {
+            let session = self.session.lock().unwrap();
+            let program = session.program.as_ref().unwrap();
+            (program.script.clone(), program.stop_on_entry)
+        };
+
+        vm.set_debug_hook(Box::new(DapHook { session: self.session.clone() }), stop_on_entry);
+        let result = vm.execute(script);
+        vm.clear_debug_hook();
+
+        let session = self.session.lock().unwrap();
+        if session.disconnected {
+            return false;
+        }
+
+        let mut client = session.client.lock().unwrap();
+        if let Err(e) = &result {
+            client.event("output", json!({ "category": "stderr", "output": format!("{}\n", e) }));
+        }
+        client.event("exited", json!({ "exitCode": if result.is_ok() { 0 } else { 1 } }));
+        client.event("terminated", json!({}));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Capture;
+
+    const PROGRAM: &str = "\
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+print x;
+";
+
+    /// Plays a scripted client against the server and returns what it sent back.
+    fn session(program: &str, name: &str, stop_on_entry: bool, breakpoints: &[u16], requests: Vec<Json>) -> Vec<Json> {
+        let path = std::env::temp_dir().join(format!("lox-dap-{}-{}.lox", std::process::id(), name));
+        fs::write(&path, program).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let breakpoints: Vec<Json> = breakpoints.iter().map(|line| json!({ "line": line })).collect();
+        let mut messages = vec![
+            json!({ "command": "initialize", "arguments": { "adapterID": "lox" } }),
+            json!({ "command": "launch", "arguments": { "program": path, "stopOnEntry": stop_on_entry } }),
+            json!({ "command": "setBreakpoints", "arguments": { "source": { "path": path }, "breakpoints": breakpoints } }),
+            json!({ "command": "configurationDone" }),
+        ];
+        messages.extend(requests);
+
+        for (seq, message) in messages.iter_mut().enumerate() {
+            message["seq"] = json!(seq + 1);
+            message["type"] = json!("request");
+        }
+
+        let output = Capture::default();
+        let input = io::Cursor::new(protocol::encode_messages(&messages));
+        DapServer::new(input, output.clone()).run().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let bytes = output.text().into_bytes();
+        protocol::decode_messages(&bytes).unwrap()
+    }
+
+    fn response<'a>(messages: &'a [Json], command: &str) -> &'a Json {
+        messages.iter().find(|m| m["type"] == "response" && m["command"] == command).unwrap()
+    }
+
+    fn events<'a>(messages: &'a [Json], event: &str) -> Vec<&'a Json> {
+        messages.iter().filter(|m| m["event"] == event).collect()
+    }
+
+    #[test]
+    fn test_breakpoint_session() {
+        let messages = session(PROGRAM, "breakpoint", false, &[3], vec![
+            json!({ "command": "threads" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 2 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 3 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "sum * 2", "frameId": 2 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        assert_eq!(response(&messages, "initialize")["body"]["supportsConfigurationDoneRequest"], true);
+        assert_eq!(events(&messages, "initialized").len(), 1);
+        assert_eq!(response(&messages, "setBreakpoints")["body"]["breakpoints"], json!([{ "verified": true, "line": 3 }]));
+
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+        let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "add");
+        assert_eq!(frames[0]["line"], 3);
+        assert_eq!(frames[1]["name"], "<script>");
+        assert_eq!(frames[1]["line"], 5);
+
+        assert_eq!(response(&messages, "scopes")["body"]["scopes"][0]["variablesReference"], 3);
+        let variables = &response(&messages, "variables")["body"]["variables"];
+        assert_eq!(variables[0]["name"], "a");
+        assert_eq!(variables[2]["name"], "sum");
+        assert_eq!(variables[2]["value"], "3");
+        assert_eq!(response(&messages, "evaluate")["body"]["result"], "6");
+
+        let output = events(&messages, "output");
+        assert_eq!(output[0]["body"]["output"], "3\n");
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(events(&messages, "terminated").len(), 1);
+        assert!(response(&messages, "disconnect")["success"].as_bool().unwrap());
+
+        let seqs: Vec<i64> = messages.iter().map(|m| m["seq"].as_i64().unwrap()).collect();
+        assert_eq!(seqs, (1..=messages.len() as i64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_unknown_frame() {
+        let messages = session(PROGRAM, "unknown-frame", false, &[3], vec![
+            json!({ "command": "evaluate", "arguments": { "expression": "1", "frameId": 0 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "1", "frameId": 3 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        ]);
+
+        let failures: Vec<&Json> = messages.iter().filter(|m| m["command"] == "evaluate" || m["command"] == "scopes").map(|m| &m["message"]).collect();
+        assert_eq!(failures, vec!["Unknown frame 0", "Unknown frame 3", "Unknown frame 0"]);
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn test_stepping_session() {
+        let messages = session(PROGRAM, "stepping", true, &[], vec![
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let reasons: Vec<&Json> = events(&messages, "stopped").iter().map(|e| &e["body"]["reason"]).collect();
+        assert_eq!(reasons, vec!["entry", "step", "step", "step"]);
+        assert_eq!(response(&messages, "stackTrace")["body"]["stackFrames"][0]["line"], 2);
+        assert_eq!(events(&messages, "exited").len(), 1);
+    }
+
+    #[test]
+    fn test_disconnect_while_paused() {
+        let messages = session(PROGRAM, "disconnect", true, &[], vec![json!({ "command": "disconnect" })]);
+
+        assert_eq!(events(&messages, "stopped").len(), 1);
+        assert!(events(&messages, "output").is_empty());
+        assert!(events(&messages, "exited").is_empty());
+    }
+
+    #[test]
+    fn test_program_reads_empty_input() {
+        let messages = session("print read_line();\nprint read_all() == \"\";\n", "input", false, &[], vec![
+            json!({ "command": "threads" }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let output: Vec<&Json> = events(&messages, "output").iter().map(|e| &e["body"]["output"]).collect();
+        assert_eq!(output, vec!["nil\n", "true\n"]);
+        assert!(response(&messages, "threads")["success"].as_bool().unwrap());
+        assert!(response(&messages, "disconnect")["success"].as_bool().unwrap());
+    }
+
+    #[test]
+    fn test_not_paused() {
+        let messages = session(PROGRAM, "not-paused", false, &[], vec![json!({ "command": "next" })]);
+
+        assert_eq!(response(&messages, "next")["success"], false);
+        assert_eq!(events(&messages, "exited").len(), 1);
+    }
+}
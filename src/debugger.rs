@@ -0,0 +1,462 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use crate::{objects::Function, value::Value, vm::VM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// How execution continues after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+    Stop,
+}
+
+/// Called by the VM whenever execution pauses at the start of a line.
+///
+/// While paused the hook may inspect the VM and evaluate expressions, but
+/// must not start another `interpret` on it.
+pub trait DebugHook: Send {
+    fn on_pause(&mut self, vm: &mut VM, reason: PauseReason) -> Resume;
+}
+
+/// A call frame as seen by a debugger, `index` 0 being the script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    pub index: usize,
+    pub function: String,
+    pub line: u16,
+}
+
+/// Breakpoints and stepping state the VM consults before each instruction.
+pub(crate) struct DebugState {
+    pub(crate) hook: Option<Box<dyn DebugHook>>,
+    pub(crate) breakpoints: HashSet<u16>,
+    resume: Resume,
+    depth: usize,
+    entry: bool,
+}
+
+impl DebugState {
+    pub(crate) fn new() -> DebugState {
+        DebugState {
+            hook: None,
+            breakpoints: HashSet::new(),
+            resume: Resume::Continue,
+            depth: 0,
+            entry: false,
+        }
+    }
+
+    pub(crate) fn attach(&mut self, hook: Box<dyn DebugHook>, stop_on_entry: bool) {
+        self.hook = Some(hook);
+        self.resume = if stop_on_entry { Resume::StepIn } else { Resume::Continue };
+        self.depth = 0;
+        self.entry = stop_on_entry;
+    }
+
+    pub(crate) fn resume(&mut self, resume: Resume, depth: usize) {
+        self.resume = resume;
+        self.depth = depth;
+    }
+
+    /// Decides whether to pause before an instruction on `line` in the frame
+    /// at `depth`, `new_line` being set when the frame just moved to the line.
+    pub(crate) fn should_pause(&mut self, depth: usize, line: u16, new_line: bool) -> Option<PauseReason> {
+        let returned = depth < self.depth;
+
+        let reason = if new_line && self.breakpoints.contains(&line) {
+            Some(PauseReason::Breakpoint)
+        } else {
+            let step = match self.resume {
+                Resume::Continue | Resume::Stop => false,
+                Resume::StepIn => new_line || returned,
+                Resume::StepOver => (new_line && depth <= self.depth) || returned,
+                Resume::StepOut => returned,
+            };
+            step.then_some(PauseReason::Step)
+        };
+
+        if reason.is_some() && std::mem::take(&mut self.entry) {
+            Some(PauseReason::Entry)
+        } else {
+            reason
+        }
+    }
+}
+
+/// Lines of `function` and the functions nested in it that hold code.
+pub fn code_lines(function: &Function) -> BTreeSet<u16> {
+    let mut lines = BTreeSet::new();
+    collect_lines(function, &mut lines);
+    lines
+}
+
+fn collect_lines(function: &Function, lines: &mut BTreeSet<u16>) {
+    lines.extend(function.block.lines.iter().copied());
+
+    for constant in function.block.constants.iter() {
+        if let Value::Function(f) = constant {
+            collect_lines(f, lines);
+        }
+    }
+}
+
+/// Moves a requested breakpoint to the first line at or after it with code.
+pub fn resolve_line(function: &Function, line: u16) -> Option<u16> {
+    code_lines(function).range(line..).next().copied()
+}
+
+/// Formats a value the way it would be written in source.
+pub fn describe(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s.value),
+        _ => value.to_string(),
+    }
+}
+
+/// The interactive debugger behind the `--debug` CLI flag.
+pub struct CliDebugger<R, W> {
+    path: String,
+    source: Vec<String>,
+    script: Arc<Function>,
+    input: R,
+    output: W,
+    frame: usize,
+}
+
+impl<R: BufRead + Send, W: Write + Send> CliDebugger<R, W> {
+    pub fn new(path: &str, source: &str, script: Arc<Function>, input: R, output: W) -> Self {
+        CliDebugger {
+            path: path.to_string(),
+            source: source.lines().map(str::to_string).collect(),
+            script,
+            input,
+            output,
+            frame: 0,
+        }
+    }
+
+    fn show_location(&mut self, frame: &FrameInfo) {
+        let name = if frame.index == 0 { "<script>".to_string() } else { format!("<fn {}>", frame.function) };
+        let _ = writeln!(self.output, "{}:{} in {}", self.path, frame.line, name);
+        self.show_line(frame.line);
+    }
+
+    fn show_line(&mut self, line: u16) {
+        if let Some(text) = self.source.get((line as usize).wrapping_sub(1)) {
+            let _ = writeln!(self.output, "{:4} | {}", line, text);
+        }
+    }
+
+    fn parse_location(&self, location: &str) -> Result<u16, String> {
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file, line)) => (Some(file), line),
+            None => (None, location),
+        };
+
+        if let Some(file) = file {
+            let matches = file == self.path || Path::new(&self.path).file_name() == Path::new(file).file_name();
+            if !matches {
+                return Err(format!("Unknown file '{}'", file));
+            }
+        }
+
+        let line: u16 = line.trim().parse().map_err(|_| format!("Invalid line '{}'", line))?;
+        resolve_line(&self.script, line).ok_or_else(|| format!("No code at or after line {}", line))
+    }
+
+    fn show_values(&mut self, values: Vec<(String, Value)>) {
+        if values.is_empty() {
+            let _ = writeln!(self.output, "(none)");
+        }
+
+        for (name, value) in values {
+            let _ = writeln!(self.output, "{} = {}", name, describe(&value));
+        }
+    }
+
+    /// Runs one command, returning how to resume if it ends the pause.
+    fn command(&mut self, vm: &mut VM, line: &str) -> Option<Resume> {
+        let (command, argument) = match line.split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "c" | "continue" => return Some(Resume::Continue),
+            "s" | "step" => return Some(Resume::StepIn),
+            "n" | "next" => return Some(Resume::StepOver),
+            "o" | "out" | "finish" => return Some(Resume::StepOut),
+            "q" | "quit" => return Some(Resume::Stop),
+            "b" | "break" => match self.parse_location(argument) {
+                Ok(line) => {
+                    vm.add_breakpoint(line);
+                    let _ = writeln!(self.output, "Breakpoint at {}:{}", self.path, line);
+                },
+                Err(msg) => { let _ = writeln!(self.output, "{}", msg); },
+            },
+            "d" | "delete" => match self.parse_location(argument) {
+                Ok(line) if vm.remove_breakpoint(line) => {
+                    let _ = writeln!(self.output, "Deleted breakpoint at {}:{}", self.path, line);
+                },
+                Ok(line) => { let _ = writeln!(self.output, "No breakpoint at {}:{}", self.path, line); },
+                Err(msg) => { let _ = writeln!(self.output, "{}", msg); },
+            },
+            "breakpoints" => {
+                for line in vm.breakpoints() {
+                    let _ = writeln!(self.output, "{}:{}", self.path, line);
+                }
+            },
+            "bt" | "backtrace" => {
+                for frame in vm.frames() {
+                    let marker = if frame.index == self.frame { "*" } else { " " };
+                    let _ = write!(self.output, "{} #{} ", marker, frame.index);
+                    self.show_location(&frame);
+                }
+            },
+            "f" | "frame" => match argument.parse::<usize>() {
+                Ok(index) if index < vm.frames().len() => {
+                    self.frame = index;
+                    let frame = vm.frames().into_iter().find(|f| f.index == index).unwrap();
+                    self.show_location(&frame);
+                },
+                _ => { let _ = writeln!(self.output, "Invalid frame '{}'", argument); },
+            },
+            "l" | "list" => {
+                if let Some(frame) = vm.frames().into_iter().find(|f| f.index == self.frame) {
+                    let first = frame.line.saturating_sub(3).max(1);
+                    for line in first..=frame.line.saturating_add(3) {
+                        self.show_line(line);
+                    }
+                }
+            },
+            "locals" => {
+                let locals = vm.locals(self.frame);
+                self.show_values(locals);
+            },
+            "globals" => {
+                let globals = vm.globals();
+                self.show_values(globals);
+            },
+            "p" | "print" => match vm.evaluate(self.frame, argument) {
+                Ok(value) => { let _ = writeln!(self.output, "{}", describe(&value)); },
+                Err(e) => { let _ = writeln!(self.output, "Error: {}", e); },
+            },
+            "h" | "help" => {
+                let _ = writeln!(self.output, "\
+break [file:]line   set a breakpoint       delete [file:]line  remove a breakpoint
+breakpoints         list breakpoints       continue            run to the next breakpoint
+step                step into calls        next                step over calls
+out                 step out of the frame  backtrace           show the call stack
+frame N             select frame N         list                show source around the frame
+locals              show locals of frame   globals             show globals
+print expr          evaluate in the frame  quit                stop the program");
+            },
+            "" => {},
+            _ => { let _ = writeln!(self.output, "Unknown command '{}', type 'help' for a list", command); },
+        }
+
+        None
+    }
+}
+
+impl<R: BufRead + Send, W: Write + Send> DebugHook for CliDebugger<R, W> {
+    fn on_pause(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+        let frames = vm.frames();
+        self.frame = frames[0].index;
+
+        let _ = write!(self.output, "Paused ({:?}) at ", reason);
+        self.show_location(&frames[0]);
+
+        loop {
+            let _ = write!(self.output, "(lox) ");
+            let _ = self.output.flush();
+
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Resume::Stop,
+                Ok(_) => {},
+            }
+
+            if let Some(resume) = self.command(vm, line.trim()) {
+                return resume;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::compile, error::LoxError, testing::Capture};
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    const PROGRAM: &str = "\
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+print x;
+";
+
+    #[derive(Debug, PartialEq)]
+    struct Pause {
+        reason: PauseReason,
+        line: u16,
+        depth: usize,
+        locals: Vec<String>,
+    }
+
+    struct ScriptedHook {
+        resumes: Vec<Resume>,
+        pauses: Arc<Mutex<Vec<Pause>>>,
+        evaluate: Option<(&'static str, Arc<Mutex<Option<Value>>>)>,
+    }
+
+    impl DebugHook for ScriptedHook {
+        fn on_pause(&mut self, vm: &mut VM, reason: PauseReason) -> Resume {
+            let frames = vm.frames();
+            let locals = vm.locals(frames[0].index).into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+
+            self.pauses.lock().unwrap().push(Pause { reason, line: frames[0].line, depth: frames.len(), locals });
+
+            if let Some((expression, result)) = self.evaluate.take() {
+                *result.lock().unwrap() = vm.evaluate(frames[0].index, expression).ok();
+            }
+
+            if self.resumes.is_empty() { Resume::Continue } else { self.resumes.remove(0) }
+        }
+    }
+
+    fn debug(source: &str, breakpoints: &[u16], stop_on_entry: bool, resumes: Vec<Resume>) -> (Result<(), LoxError>, Vec<Pause>) {
+        let pauses = Arc::new(Mutex::new(Vec::new()));
+        let hook = ScriptedHook { resumes, pauses: pauses.clone(), evaluate: None };
+
+        let mut vm = VM::new();
+        vm.set_debug_hook(Box::new(hook), stop_on_entry);
+        for line in breakpoints {
+            vm.add_breakpoint(*line);
+        }
+
+        let result = vm.interpret(source);
+        let pauses = std::mem::take(&mut *pauses.lock().unwrap());
+        (result, pauses)
+    }
+
+    fn pause(reason: PauseReason, line: u16, depth: usize, locals: &[&str]) -> Pause {
+        Pause { reason, line, depth, locals: locals.iter().map(|s| s.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_stepping() {
+        let resumes = vec![Resume::StepOver, Resume::StepIn, Resume::StepOver, Resume::StepOut, Resume::Continue];
+        let (result, pauses) = debug(PROGRAM, &[], true, resumes);
+
+        assert!(result.is_ok());
+        assert_eq!(pauses, vec![
+            pause(PauseReason::Entry, 4, 1, &[]),
+            pause(PauseReason::Step, 5, 1, &[]),
+            pause(PauseReason::Step, 2, 2, &["a=1", "b=2"]),
+            pause(PauseReason::Step, 3, 2, &["a=1", "b=2", "sum=3"]),
+            pause(PauseReason::Step, 5, 1, &[]),
+        ]);
+    }
+
+    #[test]
+    fn test_step_over_skips_calls() {
+        let resumes = vec![Resume::StepOver, Resume::StepOver, Resume::StepOver];
+        let (result, pauses) = debug(PROGRAM, &[], true, resumes);
+
+        assert!(result.is_ok());
+        // The implicit return of the script sits on the line of the end of file.
+        let lines: Vec<u16> = pauses.iter().map(|p| p.line).collect();
+        assert_eq!(lines, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let (result, pauses) = debug(PROGRAM, &[3], false, vec![]);
+
+        assert!(result.is_ok());
+        assert_eq!(pauses, vec![pause(PauseReason::Breakpoint, 3, 2, &["a=1", "b=2", "sum=3"])]);
+    }
+
+    #[test]
+    fn test_breakpoint_in_loop() {
+        let source = "var i = 0;\nwhile (i < 3) {\n    i = i + 1;\n}\n";
+        let (result, pauses) = debug(source, &[3], false, vec![]);
+
+        assert!(result.is_ok());
+        assert_eq!(pauses.len(), 3);
+    }
+
+    #[test]
+    fn test_stop() {
+        let (result, pauses) = debug(PROGRAM, &[], true, vec![Resume::Stop]);
+
+        assert!(matches!(result, Err(LoxError::Interrupted)));
+        assert_eq!(pauses.len(), 1);
+    }
+
+    #[test]
+    fn test_evaluate_in_frame() {
+        let pauses = Arc::new(Mutex::new(Vec::new()));
+        let result = Arc::new(Mutex::new(None));
+        let hook = ScriptedHook { resumes: vec![], pauses, evaluate: Some(("a + sum * 10 + x", result.clone())) };
+
+        let mut vm = VM::new();
+        vm.interpret("var x = 100;").unwrap();
+        vm.set_debug_hook(Box::new(hook), false);
+        vm.add_breakpoint(3);
+
+        assert!(vm.interpret(PROGRAM).is_ok());
+        assert_eq!(*result.lock().unwrap(), Some(Value::Number(131.0)));
+    }
+
+    #[test]
+    fn test_resolve_line() {
+        let script = compile("// comment\n\nvar a = 1;\nfun f() {\n\n    return a;\n}\n").unwrap();
+
+        assert_eq!(resolve_line(&script, 1), Some(3));
+        assert_eq!(resolve_line(&script, 5), Some(6));
+        assert_eq!(resolve_line(&script, 9), None);
+    }
+
+    #[test]
+    fn test_cli_session() {
+        let script = compile(PROGRAM).unwrap();
+        let input = Cursor::new("break add.lox:3\nbreak other.lox:1\ncontinue\nlocals\nprint sum * 2\nbt\nglobals\ncontinue\n");
+        let output = Capture::default();
+
+        let debugger = CliDebugger::new("add.lox", PROGRAM, script.clone(), input, output.clone());
+        let mut vm = VM::new();
+        vm.set_debug_hook(Box::new(debugger), true);
+
+        assert!(vm.execute(script).is_ok());
+
+        let text = output.text();
+        assert!(text.contains("Paused (Entry) at add.lox:4 in <script>"));
+        assert!(text.contains("Breakpoint at add.lox:3"));
+        assert!(text.contains("Unknown file 'other.lox'"));
+        assert!(text.contains("Paused (Breakpoint) at add.lox:3 in <fn add>\n   3 |     return sum;"));
+        assert!(text.contains("a = 1\nb = 2\nsum = 3\n"));
+        assert!(text.contains("(lox) 6\n"));
+        assert!(text.contains("* #1 add.lox:3 in <fn add>"));
+        assert!(text.contains("  #0 add.lox:5 in <script>"));
+        assert!(text.contains("add = <fn add>"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt::Write;
+use crate::{debugger::describe, instrument::function_name, objects::Function, op::OpCode, value::Value};
+
+/// Where a jump at `offset` lands, for `Jump`, `JumpIfFalse` and `Loop`.
+pub fn jump_target(instruction: OpCode, offset: usize) -> Option<usize> {
+    match instruction {
+        OpCode::Jump(jump) | OpCode::JumpIfFalse(jump) => offset.checked_add(jump as usize + 1),
+        OpCode::Loop(jump) => offset.checked_sub(jump as usize),
+        _ => None,
+    }
+}
+
+pub fn opcode_name(instruction: OpCode) -> &'static str {
+    match instruction {
+        OpCode::Constant(_) => "Constant",
+        OpCode::Nil => "Nil",
+        OpCode::True => "True",
+        OpCode::False => "False",
+        OpCode::Pop => "Pop",
+        OpCode::GetLocal(_) => "GetLocal",
+        OpCode::SetLocal(_) => "SetLocal",
+        OpCode::GetGlobal(_) => "GetGlobal",
+        OpCode::DefGlobal(_) => "DefGlobal",
+        OpCode::SetGlobal(_) => "SetGlobal",
+        OpCode::Equal => "Equal",
+        OpCode::Greater => "Greater",
+        OpCode::Less => "Less",
+        OpCode::Add => "Add",
+        OpCode::Subtract => "Subtract",
+        OpCode::Multiply => "Multiply",
+        OpCode::Divide => "Divide",
+        OpCode::Not => "Not",
+        OpCode::Negate => "Negate",
+        OpCode::Print => "Print",
+        OpCode::Jump(_) => "Jump",
+        OpCode::JumpIfFalse(_) => "JumpIfFalse",
+        OpCode::Loop(_) => "Loop",
+        OpCode::Call(_) => "Call",
+        OpCode::Return => "Return",
+    }
+}
+
+/// One line describing the instruction at `offset` of `function`: its
+/// offset, source line (`|` when unchanged), name and decoded operands.
+pub fn disassemble_instruction(function: &Function, offset: usize) -> String {
+    let block = &function.block;
+    let instruction = block.code[offset];
+    let line = block.lines[offset];
+
+    let mut text = if offset > 0 && line == block.lines[offset - 1] {
+        format!("{:04}    | {:<12}", offset, opcode_name(instruction))
+    } else {
+        format!("{:04} {:4} {:<12}", offset, line, opcode_name(instruction))
+    };
+
+    let local_name = |slot: u8| {
+        function.locals_at(offset).find(|l| l.slot == slot).map(|l| l.name.clone())
+    };
+
+    let _ = match instruction {
+        OpCode::Constant(index) => write!(text, "{:4} {}", index, describe(block.read_constant(index))),
+        OpCode::GetGlobal(index) | OpCode::DefGlobal(index) | OpCode::SetGlobal(index) => {
+            match block.read_constant(index) {
+                Value::String(name) => write!(text, "{:4} '{}'", index, name.value),
+                value => write!(text, "{:4} {}", index, describe(value)),
+            }
+        },
+        OpCode::GetLocal(slot) | OpCode::SetLocal(slot) => match local_name(slot) {
+            Some(name) => write!(text, "{:4} '{}'", slot, name),
+            None => write!(text, "{:4}", slot),
+        },
+        OpCode::Jump(jump) | OpCode::JumpIfFalse(jump) | OpCode::Loop(jump) => {
+            match jump_target(instruction, offset) {
+                Some(target) => write!(text, "{:4} -> {:04}", jump, target),
+                None => write!(text, "{:4} -> ????", jump),
+            }
+        },
+        OpCode::Call(arg_count) => write!(text, "{:4}", arg_count),
+        _ => Ok(()),
+    };
+
+    text.trim_end().to_string()
+}
+
+/// The code of `function` alone, under a header naming it.
+pub fn disassemble_function(function: &Function) -> String {
+    let block = &function.block;
+    let mut text = format!(
+        "== {} (arity {}, {} instructions, {} constants) ==\n",
+        function_name(function), function.arity, block.code.len(), block.constants.len()
+    );
+
+    for offset in 0..block.code.len() {
+        text.push_str(&disassemble_instruction(function, offset));
+        text.push('\n');
+    }
+
+    text
+}
+
+/// `function` followed by every function and test declared in it, depth first.
+pub fn disassemble(function: &Function) -> String {
+    let mut text = disassemble_function(function);
+
+    let functions = function.block.constants.iter().filter_map(|constant| match constant {
+        Value::Function(f) => Some(f),
+        _ => None,
+    });
+    for f in functions.chain(function.tests.iter()) {
+        text.push('\n');
+        text.push_str(&disassemble(f));
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    #[test]
+    fn test_disassemble_script() {
+        let script = compile("fun double(n) {\n    return n * 2;\n}\nvar i = 0;\nwhile (i < 2) i = double(i + 1);\nprint \"done\";\n").unwrap();
+
+        assert_eq!(disassemble(&script), "\
+== <script> (arity 0, 22 instructions, 11 constants) ==
+0000    3 Constant       1 <fn double>
+0001    | DefGlobal      0 'double'
+0002    4 Constant       3 0
+0003    | DefGlobal      2 'i'
+0004    5 GetGlobal      4 'i'
+0005    | Constant       5 2
+0006    | Less
+0007    | JumpIfFalse    9 -> 0017
+0008    | Pop
+0009    | GetGlobal      7 'double'
+0010    | GetGlobal      8 'i'
+0011    | Constant       9 1
+0012    | Add
+0013    | Call           1
+0014    | SetGlobal      6 'i'
+0015    | Pop
+0016    | Loop          12 -> 0004
+0017    | Pop
+0018    6 Constant      10 \"done\"
+0019    | Print
+0020    7 Nil
+0021    | Return
+
+== double (arity 1, 6 instructions, 1 constants) ==
+0000    2 GetLocal       1 'n'
+0001    | Constant       0 2
+0002    | Multiply
+0003    | Return
+0004    3 Nil
+0005    | Return
+");
+    }
+
+    #[test]
+    fn test_locals_are_named() {
+        let script = compile("fun f(a) {\n    var b = a;\n    return b;\n}\n").unwrap();
+        let f = script.block.constants.iter().find_map(|c| match c {
+            Value::Function(f) => Some(f.clone()),
+            _ => None,
+        }).unwrap();
+
+        let text = disassemble_function(&f);
+        assert!(text.starts_with("== f (arity 1, 5 instructions, 0 constants) ==\n"));
+        assert!(text.contains("0000    2 GetLocal       1 'a'\n"));
+        assert!(text.contains("0001    3 GetLocal       2 'b'\n"));
+    }
+
+    #[test]
+    fn test_jump_target() {
+        assert_eq!(jump_target(OpCode::Jump(3), 10), Some(14));
+        assert_eq!(jump_target(OpCode::Loop(5), 10), Some(5));
+        assert_eq!(jump_target(OpCode::Loop(20), 10), None);
+        assert_eq!(jump_target(OpCode::Pop, 10), None);
+    }
+}
@@ -1,6 +1,25 @@
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum LoxError {
     CompileError(String),
     RuntimeError(String),
-}
\ No newline at end of file
+    InstructionLimit(u64),
+    Timeout(Duration),
+    Interrupted,
+    OutOfMemory(usize),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::CompileError(msg) => write!(f, "{}", msg),
+            LoxError::RuntimeError(msg) => write!(f, "{}", msg),
+            LoxError::InstructionLimit(limit) => write!(f, "Instruction budget of {} exceeded", limit),
+            LoxError::Timeout(limit) => write!(f, "Execution time limit of {:?} exceeded", limit),
+            LoxError::Interrupted => write!(f, "Execution interrupted"),
+            LoxError::OutOfMemory(limit) => write!(f, "Out of memory: heap limit of {} bytes exceeded", limit),
+        }
+    }
+}
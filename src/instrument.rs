@@ -0,0 +1,27 @@
+use crate::{objects::Function, value::Value};
+
+/// Observes execution inside `VM::run`, e.g. to profile or trace a program.
+///
+/// `depth` is the number of active call frames, 1 being the script. Frames
+/// unwound by a runtime error never report a return, so instruments that
+/// keep a call stack should trim it to `depth` on every event.
+pub trait Instrument: Send {
+    /// A frame for `function` was pushed.
+    fn on_call(&mut self, _function: &Function, _depth: usize) {}
+
+    /// The frame for `function` is returning.
+    fn on_return(&mut self, _function: &Function, _depth: usize) {}
+
+    /// The instruction at `offset` in `function` is about to execute.
+    /// `stack` holds the values of the current frame, the callee first.
+    fn on_instruction(&mut self, _function: &Function, _offset: usize, _line: u16, _depth: usize, _stack: &[Value]) {}
+}
+
+/// How a function is named in reports, the script being `<script>`.
+pub fn function_name(function: &Function) -> String {
+    if function.name.value == "script" {
+        "<script>".to_string()
+    } else {
+        function.name.value.clone()
+    }
+}
@@ -0,0 +1,26 @@
+pub mod op;
+pub mod value;
+pub mod block;
+pub mod vm;
+pub mod token;
+pub mod scanner;
+pub mod compiler;
+pub mod error;
+pub mod table;
+pub mod objects;
+pub mod memory;
+pub mod stdlib;
+pub mod snapshot;
+pub mod pool;
+pub mod debugger;
+pub mod protocol;
+pub mod dap;
+pub mod lsp;
+pub mod instrument;
+pub mod profiler;
+pub mod coverage;
+pub mod disassembler;
+pub mod tracer;
+pub mod testing;
+pub mod benchmark;
+pub mod repl;
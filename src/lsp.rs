@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use serde_json::{json, Value as Json};
+use crate::{
+    compiler::{analyze, Analysis, SymbolKind}, protocol, scanner::KEYWORDS, stdlib::Module
+};
+
+// Kinds from the LSP specification.
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_KEYWORD: u8 = 14;
+const COMPLETION_CONSTANT: u8 = 21;
+const SYMBOL_FUNCTION: u8 = 12;
+const SEVERITY_ERROR: u8 = 1;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Converts between byte offsets and LSP positions, whose characters are
+/// counted in UTF-16 code units.
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> Json {
+        // Count an offset inside a character as the start of the character.
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.starts.partition_point(|start| *start <= offset) - 1;
+        let start = self.starts[line];
+        let character: usize = self.text[start..offset].chars().map(char::len_utf16).sum();
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, offset: usize, length: usize) -> Json {
+        json!({ "start": self.position(offset), "end": self.position(offset + length) })
+    }
+
+    fn offset(&self, position: &Json) -> usize {
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let Some(start) = self.starts.get(line).copied() else {
+            return self.text.len();
+        };
+
+        let mut character = position["character"].as_u64().unwrap_or(0) as usize;
+        for (i, c) in self.text[start..].char_indices() {
+            if character == 0 || c == '\n' {
+                return start + i;
+            }
+            character = character.saturating_sub(c.len_utf16());
+        }
+        self.text.len()
+    }
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let analysis = analyze(&text);
+        Document { text, analysis }
+    }
+
+    /// The declaration named by the identifier at `offset`, if any.
+    fn symbol_at(&self, offset: usize) -> Option<usize> {
+        let covers = |start: usize, length: usize| start <= offset && offset <= start + length;
+
+        let reference = self.analysis.references.iter()
+            .find(|r| covers(r.offset, r.length))
+            .and_then(|r| r.symbol);
+
+        reference.or_else(|| self.analysis.symbols.iter().position(|s| covers(s.offset, s.name.len())))
+    }
+
+    /// Ranges of every use of a symbol, optionally including its declaration.
+    fn occurrences(&self, symbol: usize, declaration: bool) -> Vec<(usize, usize)> {
+        let declared = &self.analysis.symbols[symbol];
+        let mut occurrences: Vec<_> = self.analysis.references.iter()
+            .filter(|r| r.symbol == Some(symbol))
+            .map(|r| (r.offset, r.length))
+            .collect();
+
+        if declaration {
+            occurrences.push((declared.offset, declared.name.len()));
+            occurrences.sort();
+        }
+        occurrences
+    }
+}
+
+/// A Language Server Protocol server for Lox over a byte stream.
+pub struct LspServer<R, W> {
+    input: R,
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<R: BufRead, W: Write> LspServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        LspServer { input, output, documents: HashMap::new() }
+    }
+
+    /// Serves messages until the client sends `exit` or closes the stream.
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(message) = protocol::read_message(&mut self.input)? {
+            let method = message["method"].as_str().unwrap_or("");
+            let params = &message["params"];
+
+            if method == "exit" {
+                break;
+            }
+
+            let result = match method {
+                "initialize" => Some(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "hoverProvider": true,
+                        "documentSymbolProvider": true,
+                        "completionProvider": {},
+                    },
+                    "serverInfo": { "name": "lox" },
+                })),
+                "shutdown" => Some(Json::Null),
+                "textDocument/didOpen" => {
+                    let document = &params["textDocument"];
+                    self.update(document["uri"].as_str().unwrap_or(""), document["text"].as_str().unwrap_or(""))?;
+                    None
+                },
+                "textDocument/didChange" => {
+                    // Only full document sync is advertised, so the last change holds the whole text.
+                    let text = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str());
+                    if let Some(text) = text {
+                        self.update(params["textDocument"]["uri"].as_str().unwrap_or(""), text)?;
+                    }
+                    None
+                },
+                "textDocument/didClose" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                    self.documents.remove(uri);
+                    self.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))?;
+                    None
+                },
+                "textDocument/definition" => Some(self.definition(params)),
+                "textDocument/references" => Some(self.references(params)),
+                "textDocument/hover" => Some(self.hover(params)),
+                "textDocument/documentSymbol" => Some(self.document_symbols(params)),
+                "textDocument/completion" => Some(self.completion(params)),
+                _ => None,
+            };
+
+            // Notifications have no id and never get a response.
+            let id = &message["id"];
+            if id.is_null() {
+                continue;
+            }
+
+            let response = match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": METHOD_NOT_FOUND, "message": format!("Unsupported method '{}'", method) },
+                }),
+            };
+            protocol::write_message(&mut self.output, &response)?;
+        }
+
+        Ok(())
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        protocol::write_message(&mut self.output, &json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> io::Result<()> {
+        let document = Document::new(text.to_string());
+        let index = LineIndex::new(&document.text);
+
+        let diagnostics: Vec<Json> = document.analysis.diagnostics.iter().map(|d| json!({
+            "range": index.range(d.offset, d.length),
+            "severity": SEVERITY_ERROR,
+            "source": "lox",
+            "message": d.message,
+        })).collect();
+
+        self.documents.insert(uri.to_string(), document);
+        self.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diagnostics }))
+    }
+
+    /// The document and byte offset a text document position request points at.
+    fn locate<'p>(&self, params: &'p Json) -> Option<(&'p str, &Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = LineIndex::new(&document.text).offset(&params["position"]);
+        Some((uri, document, offset))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, document, offset)) = self.locate(params) else {
+            return Json::Null;
+        };
+
+        match document.symbol_at(offset) {
+            Some(symbol) => {
+                let symbol = &document.analysis.symbols[symbol];
+                let range = LineIndex::new(&document.text).range(symbol.offset, symbol.name.len());
+                json!({ "uri": uri, "range": range })
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn references(&self, params: &Json) -> Json {
+        let Some((uri, document, offset)) = self.locate(params) else {
+            return json!([]);
+        };
+        let Some(symbol) = document.symbol_at(offset) else {
+            return json!([]);
+        };
+
+        let declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+        let index = LineIndex::new(&document.text);
+
+        document.occurrences(symbol, declaration).into_iter()
+            .map(|(offset, length)| json!({ "uri": uri, "range": index.range(offset, length) }))
+            .collect()
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((_, document, offset)) = self.locate(params) else {
+            return Json::Null;
+        };
+
+        let contents = match document.symbol_at(offset) {
+            Some(symbol) => {
+                let symbol = &document.analysis.symbols[symbol];
+                match symbol.kind {
+                    SymbolKind::Function => format!(
+                        "fun {}({})\n\narity {}", symbol.name, symbol.parameters.join(", "), symbol.parameters.len()
+                    ),
+                    SymbolKind::Parameter => format!("parameter {}", symbol.name),
+                    SymbolKind::Variable if symbol.scope.is_some() => format!("local {}", symbol.name),
+                    SymbolKind::Variable => format!("global {}", symbol.name),
+                }
+            },
+            None => {
+                let index = LineIndex::new(&document.text);
+                let word = word_at(&document.text, offset);
+                let is_native = Module::ALL.iter().flat_map(|m| m.natives()).any(|n| n.name == word);
+                let constant = Module::ALL.iter().flat_map(|m| m.constants()).find(|(name, _)| *name == word);
+
+                let contents = match constant {
+                    Some((name, value)) => format!("const {} = {}", name, value),
+                    None if is_native => format!("native fn {}", word),
+                    None => return Json::Null,
+                };
+                let start = word_start(&document.text, offset);
+                return json!({
+                    "contents": { "kind": "markdown", "value": contents },
+                    "range": index.range(start, word.len()),
+                });
+            },
+        };
+
+        json!({ "contents": { "kind": "markdown", "value": format!("```lox\n{}\n```", contents) } })
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let Some(document) = params["textDocument"]["uri"].as_str().and_then(|uri| self.documents.get(uri)) else {
+            return json!([]);
+        };
+        let index = LineIndex::new(&document.text);
+
+        document.analysis.symbols.iter()
+            .filter(|s| s.kind == SymbolKind::Function)
+            .map(|s| {
+                let range = index.range(s.offset, s.name.len());
+                json!({
+                    "name": s.name,
+                    "detail": format!("fun {}({})", s.name, s.parameters.join(", ")),
+                    "kind": SYMBOL_FUNCTION,
+                    "range": range,
+                    "selectionRange": range,
+                })
+            })
+            .collect()
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items: Vec<(String, u8)> = KEYWORDS.iter().map(|(k, _)| (k.to_string(), COMPLETION_KEYWORD)).collect();
+
+        for module in Module::ALL {
+            items.extend(module.natives().iter().map(|n| (n.name.to_string(), COMPLETION_FUNCTION)));
+            items.extend(module.constants().iter().map(|(name, _)| (name.to_string(), COMPLETION_CONSTANT)));
+        }
+
+        if let Some((_, document, offset)) = self.locate(params) {
+            let visible = document.analysis.symbols.iter().filter(|s| match s.scope {
+                Some((start, end)) => start <= offset && offset <= end,
+                None => true,
+            });
+
+            for symbol in visible {
+                let kind = if symbol.kind == SymbolKind::Function { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE };
+                items.push((symbol.name.clone(), kind));
+            }
+        }
+
+        items.sort();
+        items.dedup_by(|a, b| a.0 == b.0);
+
+        items.into_iter().map(|(label, kind)| json!({ "label": label, "kind": kind })).collect()
+    }
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn word_start(text: &str, offset: usize) -> usize {
+    text[..offset].char_indices().rev()
+        .find(|(_, c)| !is_identifier(*c))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+/// The identifier around `offset`.
+fn word_at(text: &str, offset: usize) -> &str {
+    let start = word_start(text, offset);
+    let end = text[offset..].find(|c| !is_identifier(c)).map_or(text.len(), |i| offset + i);
+    &text[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///add.lox";
+
+    const PROGRAM: &str = "\
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+print add(x, clock());
+";
+
+    fn position(line: usize, character: usize) -> Json {
+        json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+    }
+
+    /// Opens `PROGRAM`, plays `requests` and returns everything the server sent.
+    fn session(requests: Vec<(&str, Json)>) -> Vec<Json> {
+        session_with(PROGRAM, requests)
+    }
+
+    fn session_with(text: &str, requests: Vec<(&str, Json)>) -> Vec<Json> {
+        let mut messages = vec![
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": text },
+            }}),
+        ];
+
+        for (id, (method, params)) in requests.into_iter().enumerate() {
+            let mut message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+            if !method.starts_with("textDocument/did") {
+                message["id"] = json!(id + 1);
+            }
+            messages.push(message);
+        }
+        messages.push(json!({ "jsonrpc": "2.0", "id": 99, "method": "shutdown" }));
+        messages.push(json!({ "jsonrpc": "2.0", "method": "exit" }));
+
+        let mut output = Vec::new();
+        let input = protocol::encode_messages(&messages);
+        LspServer::new(&input[..], &mut output).run().unwrap();
+        protocol::decode_messages(&output).unwrap()
+    }
+
+    fn result(messages: &[Json], id: usize) -> &Json {
+        &messages.iter().find(|m| m["id"] == id).unwrap()["result"]
+    }
+
+    fn diagnostics(messages: &[Json]) -> Vec<&Json> {
+        messages.iter().filter(|m| m["method"] == "textDocument/publishDiagnostics").collect()
+    }
+
+    fn range(start: (usize, usize), end: (usize, usize)) -> Json {
+        json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        })
+    }
+
+    #[test]
+    fn test_initialize_and_shutdown() {
+        let messages = session(vec![]);
+
+        assert_eq!(result(&messages, 0)["capabilities"]["definitionProvider"], true);
+        assert_eq!(result(&messages, 99), &Json::Null);
+        assert_eq!(diagnostics(&messages)[0]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_diagnostics_on_change() {
+        let messages = session(vec![("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "var x = 1;\nprint x +;\n" }],
+        }))]);
+
+        let published = diagnostics(&messages);
+        assert_eq!(published.len(), 2);
+
+        let errors = published[1]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["message"], "Expected expression.");
+        assert_eq!(errors[0]["range"], range((1, 9), (1, 10)));
+    }
+
+    #[test]
+    fn test_non_ascii_diagnostics() {
+        let messages = session_with("var é = 1;", vec![]);
+
+        let errors = diagnostics(&messages)[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(errors[0]["message"], "Unknown token.");
+        assert_eq!(errors[0]["range"], range((0, 4), (0, 5)));
+    }
+
+    #[test]
+    fn test_definition() {
+        let messages = session(vec![
+            ("textDocument/definition", position(2, 12)),
+            ("textDocument/definition", position(5, 7)),
+            ("textDocument/definition", position(4, 1)),
+        ]);
+
+        assert_eq!(result(&messages, 1), &json!({ "uri": URI, "range": range((1, 8), (1, 11)) }));
+        assert_eq!(result(&messages, 2)["range"], range((0, 4), (0, 7)));
+        assert_eq!(result(&messages, 3), &Json::Null);
+    }
+
+    #[test]
+    fn test_references() {
+        let mut without_declaration = position(0, 5);
+        without_declaration["context"] = json!({ "includeDeclaration": false });
+
+        let messages = session(vec![
+            ("textDocument/references", position(1, 14)),
+            ("textDocument/references", without_declaration),
+        ]);
+
+        let locals = result(&messages, 1).as_array().unwrap();
+        assert_eq!(locals.len(), 2);
+        assert_eq!(locals[0]["range"], range((0, 8), (0, 9)));
+        assert_eq!(locals[1]["range"], range((1, 14), (1, 15)));
+
+        let calls: Vec<&Json> = result(&messages, 2).as_array().unwrap().iter().map(|r| &r["range"]).collect();
+        assert_eq!(calls, vec![&range((4, 8), (4, 11)), &range((5, 6), (5, 9))]);
+    }
+
+    #[test]
+    fn test_hover() {
+        let messages = session(vec![
+            ("textDocument/hover", position(4, 9)),
+            ("textDocument/hover", position(5, 16)),
+            ("textDocument/hover", position(3, 0)),
+        ]);
+
+        assert_eq!(result(&messages, 1)["contents"]["value"], "```lox\nfun add(a, b)\n\narity 2\n```");
+        assert_eq!(result(&messages, 2)["contents"]["value"], "native fn clock");
+        assert_eq!(result(&messages, 3), &Json::Null);
+    }
+
+    #[test]
+    fn test_non_ascii_hover() {
+        let messages = session_with("print \"日clock\";", vec![("textDocument/hover", position(0, 9))]);
+
+        assert_eq!(result(&messages, 1)["contents"]["value"], "native fn clock");
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let messages = session(vec![("textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } }))]);
+
+        let symbols = result(&messages, 1).as_array().unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0]["name"], "add");
+        assert_eq!(symbols[0]["kind"], SYMBOL_FUNCTION);
+        assert_eq!(symbols[0]["selectionRange"], range((0, 4), (0, 7)));
+    }
+
+    #[test]
+    fn test_completion() {
+        let messages = session(vec![
+            ("textDocument/completion", position(2, 4)),
+            ("textDocument/completion", position(5, 0)),
+        ]);
+
+        let labels = |id| -> Vec<String> {
+            result(&messages, id).as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap().to_string()).collect()
+        };
+
+        let inside = labels(1);
+        for label in ["sum", "a", "b", "add", "x", "while", "clock", "sqrt", "PI"] {
+            assert!(inside.contains(&label.to_string()), "missing {}", label);
+        }
+
+        let outside = labels(2);
+        assert!(outside.contains(&"add".to_string()));
+        assert!(!outside.contains(&"sum".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_request() {
+        let messages = session(vec![("workspace/symbol", json!({ "query": "" }))]);
+
+        let response = messages.iter().find(|m| m["id"] == 1).unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_line_index() {
+        let text = "é = 1;\nvar 😀x;";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.position(text.find('x').unwrap()), json!({ "line": 1, "character": 6 }));
+        assert_eq!(index.offset(&json!({ "line": 1, "character": 6 })), text.find('x').unwrap());
+        assert_eq!(index.offset(&json!({ "line": 0, "character": 99 })), text.find('\n').unwrap());
+    }
+}
@@ -1,63 +1,425 @@
-mod op;
-mod value;
-mod block;
-mod vm;
-mod token;
-mod scanner;
-mod compiler;
-mod error;
-mod table;
-mod objects;
-
-use vm::{VM};
-use std::io::{stdin, stdout, Write};
+use tapssp_project::vm::{VM};
+use tapssp_project::{error::LoxError, compiler::compile, dap::DapServer, debugger::CliDebugger, lsp::LspServer, profiler::Profiler, coverage::Coverage, disassembler, tracer::Tracer, testing::run_tests, repl::{self, Command}, value::Value};
+use std::io::{self, stdin, stdout, BufReader, BufWriter, Write};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+
+const USAGE: &str = "\
+Usage: tapssp-project [<script> | -e <code> | -] [<args>...]
+       tapssp-project test <script or directory>...
+       tapssp-project lsp
+       tapssp-project --dap
+       tapssp-project --debug|--disassemble <script>
+       tapssp-project --profile [--folded <stacks.folded>] <script>
+       tapssp-project --trace <trace.jsonl> [--function <name>]... <script>
+       tapssp-project --coverage [--coverage-out <lcov.info>] <script>...
+
+Runs the script, the code given to -e, or with -, the script read from
+standard input. The arguments after it are passed to the script through
+args(). Starts a REPL when run without arguments.";
+
+// Exit codes from sysexits.h.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(EX_USAGE);
+}
+
+/// Reads a script, from standard input if `filename` is `-`, exiting if it
+/// cannot be read.
+fn read_source(filename: &str) -> String {
+    let source = if filename == "-" { io::read_to_string(stdin()) } else { fs::read_to_string(filename) };
+
+    source.unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", filename, e);
+        process::exit(EX_NOINPUT);
+    })
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(".lox_history"))
+}
+
+fn run_command(command: Command, vm: &mut VM) {
+    match command {
+        Command::Load(path) => match fs::read_to_string(&path) {
+            Ok(source) => match vm.interpret(&source) {
+                Err(e) => println!("{:?}", e),
+                Ok(value) => println!("{:?}", value),
+            },
+            Err(e) => println!("Could not read {}: {}", path, e),
+        },
+        Command::Reset => *vm = VM::new(),
+        Command::Globals => {
+            for (name, value) in vm.globals() {
+                if !matches!(value, Value::NativeFunction(_)) {
+                    println!("{} = {}", name, value);
+                }
+            }
+        },
+        Command::Dis(code) => {
+            if let Ok(function) = compile(&repl::as_statement(&code)) {
+                print!("{}", disassembler::disassemble(&function));
+            }
+        },
+        Command::Time(code) => {
+            let start = Instant::now();
+            let result = vm.interpret(&repl::as_statement(&code));
+            let elapsed = start.elapsed();
+
+            if let Err(e) = result {
+                println!("{:?}", e);
+            }
+            println!("{:.2?}, {} instructions", elapsed, vm.instruction_count());
+        },
+        Command::Trace(on) => vm.set_trace(on),
+        Command::Help => println!("{}", repl::HELP),
+    }
+}
+
+fn global_names(vm: &VM) -> Vec<String> {
+    vm.globals().into_iter().map(|(name, _)| name).collect()
+}
 
 fn repl() {
-    let mut line = String::new();
+    let mut input = String::new();
 
     let mut vm = VM::new();
+    let mut editor: Editor<repl::LoxHelper, DefaultHistory> = Editor::new().expect("Could not start line editor");
+    editor.set_helper(Some(repl::LoxHelper { globals: global_names(&vm) }));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history yet on the first run.
+        let _ = editor.load_history(path);
+    }
 
     loop {
-        print!("> ");
-        stdout().flush().unwrap();
+        let mut line = match editor.readline(if input.is_empty() { "> " } else { "... " }) {
+            Ok(line) => line,
+            // Ctrl-C drops what has been typed so far.
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            },
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            },
+        };
+        line.push('\n');
 
-        line.clear();
-        let bytes = stdin().read_line(&mut line).unwrap();
-        if bytes == 0 {
-            break;
+        if input.is_empty() {
+            let command = line.trim();
+            if command.eq_ignore_ascii_case("quit") || command.eq_ignore_ascii_case("exit") {
+                break;
+            }
+
+            if let Some(parsed) = Command::parse(command) {
+                let _ = editor.add_history_entry(command);
+                match parsed {
+                    Ok(command) => run_command(command, &mut vm),
+                    Err(message) => println!("{}", message),
+                }
+
+                if let Some(helper) = editor.helper_mut() {
+                    helper.globals = global_names(&vm);
+                }
+                continue;
+            }
         }
 
-        let input = line.trim();
-        if input.eq_ignore_ascii_case("quit") || input.eq_ignore_ascii_case("exit") {
-            break;
+        // An empty continuation line compiles what was typed so far, errors and all.
+        let forced = !input.is_empty() && line.trim().is_empty();
+        input.push_str(&line);
+        if !forced && repl::is_incomplete(&input) {
+            continue;
         }
 
-        match vm.interpret(input) {
+        let _ = editor.add_history_entry(input.trim_end());
+        match vm.interpret(&input) {
             Err(e) => println!("{:?}", e),
             Ok(value) => println!("{:?}", value),
         }
+        input.clear();
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.globals = global_names(&vm);
+        }
     }
 
+    if let Some(path) = &history && let Err(e) = editor.save_history(path) {
+        eprintln!("Could not save history to {}: {}", path.display(), e);
+    }
     println!("Exiting.");
 }
 
-fn run_file(filename: &str) {
-    let source = fs::read_to_string(filename)
-        .expect("Could not read file");
+/// Runs a script with `args` as its arguments, exiting with 65 if it does
+/// not compile and 70 if it fails at runtime.
+fn run_script(source: &str, args: &[String]) -> ! {
+    let mut vm = VM::new();
+    vm.set_args(args.to_vec());
+
+    let code = match vm.interpret(source) {
+        Ok(()) => 0,
+        // The compiler has already reported the errors.
+        Err(LoxError::CompileError(_)) => EX_DATAERR,
+        Err(e) => {
+            eprintln!("{}", e);
+            for frame in vm.error_frames() {
+                eprintln!("[line {}] in {}", frame.line, frame.function);
+            }
+            EX_SOFTWARE
+        },
+    };
+
+    let _ = stdout().flush();
+    process::exit(code);
+}
+
+fn debug_file(filename: &str) {
+    let source = read_source(filename);
+
+    let script = match compile(&source) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
 
+    let debugger = CliDebugger::new(filename, &source, script.clone(), BufReader::new(stdin()), stdout());
     let mut vm = VM::new();
+    vm.set_debug_hook(Box::new(debugger), true);
+    // The debugger reads its commands from stdin, so the program gets none.
+    vm.set_input(Box::new(io::empty()));
 
-    match vm.interpret(&source) {
+    match vm.execute(script) {
         Err(e) => println!("{:?}", e),
         Ok(value) => println!("{:?}", value),
     }
 }
 
-fn main() {
-    if let Some(arg) = std::env::args().nth(1) {
-        run_file(&arg);
+fn profile_file(args: &[String]) {
+    let (folded, filename) = match args {
+        [flag, folded, filename] if flag == "--folded" => (Some(folded), filename),
+        [filename] => (None, filename),
+        _ => {
+            eprintln!("Usage: --profile [--folded <stacks.folded>] <script>");
+            return;
+        }
+    };
+
+    let source = read_source(filename);
+
+    let profiler = Profiler::new();
+    let mut vm = VM::new();
+    vm.add_instrument(Box::new(profiler.clone()));
+
+    if let Err(e) = vm.interpret(&source) {
+        eprintln!("{}", e);
+    }
+
+    let profile = profiler.profile();
+    eprint!("{}", profile.report());
+
+    let Some(folded) = folded else {
+        return;
+    };
+    match fs::write(folded, profile.folded()) {
+        Ok(()) => eprintln!("\nFolded stacks written to {}", folded),
+        Err(e) => eprintln!("\nCould not write {}: {}", folded, e),
+    }
+}
+
+fn cover_files(args: &[String]) {
+    let (lcov, filenames) = match args {
+        [flag, lcov, filenames @ ..] if flag == "--coverage-out" => (Some(lcov), filenames),
+        filenames => (None, filenames),
+    };
+    if filenames.is_empty() {
+        eprintln!("Usage: --coverage [--coverage-out <lcov.info>] <script>...");
+        return;
+    }
+
+    let coverage = Coverage::new();
+
+    for filename in filenames {
+        let source = read_source(filename);
+
+        let script = match compile(&source) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        coverage.add_script(filename, &script);
+
+        let mut vm = VM::new();
+        vm.add_instrument(Box::new(coverage.clone()));
+        if let Err(e) = vm.execute(script) {
+            eprintln!("{}", e);
+        }
+    }
+
+    for file in coverage.files() {
+        eprintln!("{}", file.summary());
+    }
+
+    let Some(lcov) = lcov else {
+        return;
+    };
+    match fs::write(lcov, coverage.lcov()) {
+        Ok(()) => eprintln!("Coverage written to {}", lcov),
+        Err(e) => eprintln!("Could not write {}: {}", lcov, e),
+    }
+}
+
+fn trace_file(trace: &str, args: &[String]) {
+    let mut functions = Vec::new();
+    let mut args = args;
+    while let [flag, function, rest @ ..] = args && flag == "--function" {
+        functions.push(function.clone());
+        args = rest;
+    }
+
+    let [filename] = args else {
+        eprintln!("Usage: --trace <trace.jsonl> [--function <name>]... <script>");
+        return;
+    };
+
+    let source = read_source(filename);
+
+    let output = match fs::File::create(trace) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("Could not create {}: {}", trace, e);
+            return;
+        }
+    };
+
+    let tracer = if functions.is_empty() {
+        Tracer::new(output)
     } else {
-        repl();
+        Tracer::filtered(output, &functions)
+    };
+    let mut vm = VM::new();
+    vm.add_instrument(Box::new(tracer.clone()));
+
+    if let Err(e) = vm.interpret(&source) {
+        println!("{:?}", e);
+    }
+
+    match tracer.finish() {
+        Ok(()) => eprintln!("Trace written to {}", trace),
+        Err(e) => eprintln!("Could not write {}: {}", trace, e),
+    }
+}
+
+fn disassemble_file(filename: &str) {
+    let source = read_source(filename);
+
+    match compile(&source) {
+        Ok(script) => print!("{}", disassembler::disassemble(&script)),
+        Err(e) => println!("{:?}", e),
+    }
+}
+
+fn lox_files(path: &Path, files: &mut Vec<String>) {
+    if !path.is_dir() {
+        files.push(path.display().to_string());
+        return;
+    }
+
+    let mut entries: Vec<_> = fs::read_dir(path)
+        .expect("Could not read directory")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "lox") {
+            lox_files(&entry, files);
+        }
+    }
+}
+
+fn test_files(paths: &[String]) {
+    if paths.is_empty() {
+        eprintln!("Usage: test <script or directory>...");
+        process::exit(2);
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        lox_files(Path::new(path), &mut files);
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for filename in &files {
+        let source = read_source(filename);
+
+        let script = match compile(&source) {
+            Ok(script) => script,
+            Err(e) => {
+                println!("{}: {:?}\n", filename, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if script.tests.is_empty() {
+            continue;
+        }
+
+        let report = run_tests(filename, &script, VM::new);
+        println!("{}", report.report());
+        passed += report.passed();
+        failed += report.failed();
+    }
+
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    println!("test result: {}. {} passed; {} failed", status, passed, failed);
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.as_slice() {
+        [command, paths @ ..] if command == "test" => test_files(paths),
+        [command] if command == "lsp" => {
+            if let Err(e) = LspServer::new(stdin().lock(), stdout()).run() {
+                eprintln!("{}", e);
+            }
+        },
+        [flag, ..] if flag == "--dap" => {
+            if let Err(e) = DapServer::new(BufReader::new(stdin()), stdout()).run() {
+                eprintln!("{}", e);
+            }
+        },
+        [flag, file, ..] if flag == "--debug" => debug_file(file),
+        [flag, args @ ..] if flag == "--profile" => profile_file(args),
+        [flag, file, ..] if flag == "--disassemble" => disassemble_file(file),
+        [flag, trace, rest @ ..] if flag == "--trace" && !rest.is_empty() => trace_file(trace, rest),
+        [flag, args @ ..] if flag == "--coverage" => cover_files(args),
+        [flag] if flag == "-h" || flag == "--help" => println!("{}", USAGE),
+        [flag, code, rest @ ..] if flag == "-e" => run_script(code, rest),
+        [flag] if flag == "-e" => usage_error("Missing code after -e"),
+        [flag, rest @ ..] if flag == "-" => run_script(&read_source(flag), rest),
+        [flag, ..] if flag.starts_with('-') => usage_error(&format!("Unknown option {}", flag)),
+        [file, rest @ ..] => run_script(&read_source(file), rest),
+        [] => repl(),
     }
 }
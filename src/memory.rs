@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use crate::{
+    objects::{Function, LocalInfo, LoxString, ObjectType}, op::OpCode, value::Value
+};
+
+/// Snapshot of the heap accounting of a `VM`, all sizes in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub bytes_in_use: usize,
+    pub bytes_allocated: usize,
+    pub peak_bytes: usize,
+    pub total_allocated: usize,
+    pub strings_allocated: usize,
+    pub functions_allocated: usize,
+    pub limit: Option<usize>,
+}
+
+/// Tracks the estimated size of the objects allocated by a `VM`.
+///
+/// Objects are reference counted, so frees are not observed directly.
+/// `bytes_allocated` only grows until the owner re-measures the live heap
+/// and calls `sync`, the same way a tracing collector resets its counter.
+#[derive(Debug, Default)]
+pub struct Heap {
+    bytes_allocated: usize,
+    peak_bytes: usize,
+    total_allocated: usize,
+    strings_allocated: usize,
+    functions_allocated: usize,
+    limit: Option<usize>,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::default()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn fits(&self, size: usize) -> bool {
+        match self.limit {
+            Some(limit) => self.bytes_allocated.saturating_add(size) <= limit,
+            None => true,
+        }
+    }
+
+    pub fn track(&mut self, object_type: ObjectType, size: usize) {
+        match object_type {
+            ObjectType::LoxString => self.strings_allocated += 1,
+            ObjectType::Function => self.functions_allocated += 1,
+            ObjectType::Native => {},
+        }
+
+        self.bytes_allocated += size;
+        self.total_allocated += size;
+        self.peak_bytes = self.peak_bytes.max(self.bytes_allocated);
+    }
+
+    pub fn sync(&mut self, live_bytes: usize) {
+        self.bytes_allocated = live_bytes;
+    }
+
+    pub fn stats(&self, bytes_in_use: usize) -> MemoryStats {
+        MemoryStats {
+            bytes_in_use,
+            bytes_allocated: self.bytes_allocated,
+            peak_bytes: self.peak_bytes,
+            total_allocated: self.total_allocated,
+            strings_allocated: self.strings_allocated,
+            functions_allocated: self.functions_allocated,
+            limit: self.limit,
+        }
+    }
+}
+
+pub fn string_size(len: usize) -> usize {
+    size_of::<LoxString>() + len
+}
+
+/// Size of a function itself, excluding the objects in its constant table.
+pub fn function_size(function: &Function) -> usize {
+    let block = &function.block;
+
+    size_of::<Function>()
+        + block.code.len() * size_of::<OpCode>()
+        + block.constants.len() * size_of::<Value>()
+        + block.lines.len() * size_of::<u16>()
+        + function.locals.len() * size_of::<LocalInfo>()
+}
+
+/// Measures the heap objects reachable from a set of roots, counting
+/// objects shared between several roots only once.
+#[derive(Default)]
+pub struct Tracer {
+    seen: HashSet<usize>,
+    bytes: usize,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer::default()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn mark_string(&mut self, s: &LoxString) {
+        if self.seen.insert(s as *const LoxString as usize) {
+            self.bytes += string_size(s.value.len());
+        }
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        match value {
+            Value::String(s) => self.mark_string(s),
+            Value::Function(f) => self.mark_function(f),
+            _ => {},
+        }
+    }
+
+    pub fn mark_function(&mut self, function: &Function) {
+        if !self.seen.insert(function as *const Function as usize) {
+            return;
+        }
+
+        self.bytes += function_size(function);
+        self.mark_string(&function.name);
+
+        for constant in function.block.constants.iter() {
+            self.mark_value(constant);
+        }
+    }
+}
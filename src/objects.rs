@@ -1,8 +1,9 @@
 use crate::block::Block;
 use core::fmt;
 use std::fmt::Display;
-use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::error::LoxError;
 use crate::vm::VM;
 use crate::value::Value;
 
@@ -25,17 +26,17 @@ impl Display for LoxString {
 }
 
 impl LoxString {
-    pub fn new(value: &str) -> Rc<LoxString> {
-        let hash = LoxString::hash(&value);
+    pub fn new(value: &str) -> Arc<LoxString> {
+        let hash = LoxString::hash(value);
         let s = LoxString { 
             value: value.to_string(), 
             hash,
         };
 
-        Rc::new(s)
+        Arc::new(s)
     }
 
-    pub fn from_string(s: &str) -> Rc<LoxString> {
+    pub fn from_string(s: &str) -> Arc<LoxString> {
         LoxString::new(s)
     }
 
@@ -49,25 +50,47 @@ impl LoxString {
     }
 }
 
+/// Names the local stored in `slot` of a function's frame while the
+/// instruction offset is in `start..end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalInfo {
+    pub name: String,
+    pub slot: u8,
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
-    pub name: Rc<LoxString>,
+    pub name: Arc<LoxString>,
     pub block: Block,
     pub arity: usize,
+    pub locals: Vec<LocalInfo>,
+    /// The `test "name" { ... }` declarations of a script, in source order.
+    pub tests: Vec<Arc<Function>>,
 }
 
 impl Function {
-    pub fn new(function_name: Rc<LoxString>) -> Box<Function> {
+    pub fn new(function_name: Arc<LoxString>) -> Box<Function> {
         let f = Function {
             name: function_name,
             block: Block::new(),
             arity: 0,
+            locals: Vec::new(),
+            tests: Vec::new(),
         };
 
         Box::new(f)
     }
 }
 
+impl Function {
+    /// Locals in scope at `offset`, innermost declaration first.
+    pub fn locals_at(&self, offset: usize) -> impl Iterator<Item = &LocalInfo> {
+        self.locals.iter().rev().filter(move |local| local.start <= offset && offset < local.end)
+    }
+}
+
 impl Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.name.value == "script" {
@@ -78,10 +101,44 @@ impl Display for Function {
     }
 }
 
+/// A native gets its arguments in place on the VM's stack, so one that
+/// calls back into the VM must copy the arguments it uses afterwards.
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, LoxError>;
+
 #[derive(Clone, Copy)]
-pub struct NativeFunction (
-    pub fn(&VM, &[Value]) -> Value
-);
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub function: NativeFn,
+    /// The fewest and the most arguments the native takes, checked by the
+    /// VM before calling it.
+    pub arity: (usize, usize),
+}
+
+impl NativeFunction {
+    pub const fn new(name: &'static str, arity: usize, function: NativeFn) -> NativeFunction {
+        NativeFunction { name, function, arity: (arity, arity) }
+    }
+
+    /// A native with optional arguments, `max` being `usize::MAX` if it
+    /// takes any number of them.
+    pub const fn variadic(name: &'static str, min: usize, max: usize, function: NativeFn) -> NativeFunction {
+        NativeFunction { name, function, arity: (min, max) }
+    }
+
+    pub fn check_arity(&self, arg_count: usize) -> Result<(), LoxError> {
+        let (min, max) = self.arity;
+
+        if (min..=max).contains(&arg_count) {
+            Ok(())
+        } else if max == usize::MAX {
+            Err(LoxError::RuntimeError(format!("Expected at least {} arguments but got {}", min, arg_count)))
+        } else if min == max {
+            Err(LoxError::RuntimeError(format!("Expected {} arguments but got {}", min, arg_count)))
+        } else {
+            Err(LoxError::RuntimeError(format!("Expected {} to {} arguments but got {}", min, max, arg_count)))
+        }
+    }
+}
 
 impl fmt::Debug for NativeFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -91,6 +148,6 @@ impl fmt::Debug for NativeFunction {
 
 impl PartialEq for NativeFunction {
     fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(&self, &other)
+        self.name == other.name
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,181 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use crate::{error::LoxError, vm::VM};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PoolError {
+    #[error("The pool has no workers left")]
+    Stopped,
+    #[error("Job panicked: {0}")]
+    Panicked(String),
+}
+
+/// Receives the result of a job, or the message of the panic that ended it.
+pub type JobReceiver<R> = mpsc::Receiver<Result<R, PoolError>>;
+
+/// A job for a worker, returning false if it panicked.
+type Job = Box<dyn FnOnce(&mut VM) -> bool + Send>;
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string()),
+    }
+}
+
+/// A fixed set of worker threads, each owning an independent `VM`.
+///
+/// Jobs are handed to whichever worker is free. A worker keeps its VM
+/// between jobs, so globals defined by one job are visible to later jobs
+/// that land on the same worker. A job that panics fails with
+/// `PoolError::Panicked`, and its worker carries on with a fresh VM.
+pub struct VmPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl VmPool {
+    /// Starts `size` workers, moving a VM built by `factory` into each.
+    pub fn new(size: usize, factory: impl Fn() -> VM + Send + Sync + 'static) -> VmPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let factory = Arc::new(factory);
+
+        let workers = (0..size).map(|_| {
+            let mut vm = factory();
+            let receiver = receiver.clone();
+            let factory = factory.clone();
+
+            thread::spawn(move || {
+                loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // A panic can leave the VM in the middle of a run.
+                        Ok(job) => if !job(&mut vm) {
+                            vm = factory();
+                        },
+                        Err(_) => break,
+                    }
+                }
+            })
+        }).collect();
+
+        VmPool { sender: Some(sender), workers }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `job` on the next free worker and returns a channel that
+    /// receives its result, or the panic message if it panicked.
+    pub fn execute<F, R>(&self, job: F) -> Result<JobReceiver<R>, PoolError>
+    where
+        F: FnOnce(&mut VM) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_sender, result_receiver) = mpsc::channel();
+
+        let job: Job = Box::new(move |vm| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| job(vm)))
+                .map_err(|payload| PoolError::Panicked(panic_message(&*payload)));
+            let finished = result.is_ok();
+
+            let _ = result_sender.send(result);
+            finished
+        });
+        self.sender.as_ref().unwrap().send(job).map_err(|_| PoolError::Stopped)?;
+
+        Ok(result_receiver)
+    }
+
+    pub fn interpret(&self, source: &str) -> Result<JobReceiver<Result<(), LoxError>>, PoolError> {
+        let source = source.to_string();
+        self.execute(move |vm| vm.interpret(&source))
+    }
+}
+
+impl Drop for VmPool {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    const FIB: &str = include_str!("../native_function.lox");
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_vm_is_send() {
+        assert_send::<VM>();
+        assert_send::<Value>();
+    }
+
+    #[test]
+    fn test_threads_run_fib() {
+        let handles: Vec<_> = (0..4).map(|_| {
+            let mut vm = VM::new();
+
+            thread::spawn(move || {
+                vm.interpret(FIB).unwrap();
+                vm.interpret("var result = fib(15);").unwrap();
+                vm.get_global("result")
+            })
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Some(Value::Number(610.0)));
+        }
+    }
+
+    #[test]
+    fn test_pool_runs_jobs_in_parallel() {
+        let pool = VmPool::new(4, VM::new);
+        assert_eq!(pool.size(), 4);
+
+        let results: Vec<_> = (0..8).map(|n| {
+            pool.execute(move |vm| {
+                vm.interpret(FIB)?;
+                vm.interpret(&format!("var result = fib({});", n + 10))?;
+                Ok::<_, LoxError>(vm.get_global("result"))
+            }).unwrap()
+        }).collect();
+
+        let expected = [55.0, 89.0, 144.0, 233.0, 377.0, 610.0, 987.0, 1597.0];
+        for (result, expected) in results.into_iter().zip(expected) {
+            assert_eq!(result.recv().unwrap().unwrap().unwrap(), Some(Value::Number(expected)));
+        }
+    }
+
+    #[test]
+    fn test_pool_interpret() {
+        let pool = VmPool::new(2, VM::sandboxed);
+
+        assert!(pool.interpret("var x = 1 + 2;").unwrap().recv().unwrap().unwrap().is_ok());
+        assert!(matches!(pool.interpret("-nil;").unwrap().recv().unwrap(), Ok(Err(LoxError::RuntimeError(_)))));
+    }
+
+    #[test]
+    fn test_panicking_job() {
+        let pool = VmPool::new(1, VM::new);
+        pool.interpret("var x = 1;").unwrap().recv().unwrap().unwrap().unwrap();
+
+        let result = pool.execute(|_| -> () { panic!("job failed") }).unwrap().recv().unwrap();
+        assert_eq!(result, Err(PoolError::Panicked("job failed".to_string())));
+
+        // The worker is still running, with a fresh VM.
+        let x = pool.execute(|vm| vm.get_global("x")).unwrap().recv().unwrap();
+        assert_eq!(x, Ok(None));
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::{instrument::{self, Instrument}, objects::Function, value::Value};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineProfile {
+    pub line: u16,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+/// The results of a profiling run, each list sorted by instructions, highest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub instructions: u64,
+    pub time: Duration,
+    pub functions: Vec<FunctionProfile>,
+    pub lines: Vec<LineProfile>,
+    /// Call stacks, outermost function first, with the instructions run in them.
+    pub stacks: Vec<(Vec<String>, u64)>,
+}
+
+impl Profile {
+    /// A human readable summary of where the instructions and time went.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "{} instructions in {:?}", self.instructions, self.time);
+
+        let _ = writeln!(report, "\n{:>8} {:>12} {:>12} {:>7}  function", "calls", "instructions", "time", "%");
+        for f in &self.functions {
+            let _ = writeln!(
+                report, "{:>8} {:>12} {:>12} {:>6.2}%  {}",
+                f.calls, f.instructions, format!("{:.2?}", f.time), self.percent(f.instructions), f.name
+            );
+        }
+
+        let _ = writeln!(report, "\n{:>8} {:>12} {:>12} {:>7}", "line", "instructions", "time", "%");
+        for l in &self.lines {
+            let _ = writeln!(
+                report, "{:>8} {:>12} {:>12} {:>6.2}%",
+                l.line, l.instructions, format!("{:.2?}", l.time), self.percent(l.instructions)
+            );
+        }
+
+        report
+    }
+
+    /// Stacks in the folded format read by flamegraph tools, one
+    /// `outer;inner count` line per stack.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, count) in &self.stacks {
+            let _ = writeln!(folded, "{} {}", stack.join(";"), count);
+        }
+        folded
+    }
+
+    fn percent(&self, instructions: u64) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            instructions as f64 * 100.0 / self.instructions as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    instructions: u64,
+    time: Duration,
+}
+
+impl Samples {
+    fn add(&mut self, instructions: u64, time: Duration) {
+        self.instructions += instructions;
+        self.time += time;
+    }
+}
+
+#[derive(Default)]
+struct State {
+    interval: u64,
+    countdown: u64,
+    last_sample: Option<Instant>,
+    total: Samples,
+    names: Vec<String>,
+    // Functions with the same name are reported together.
+    by_name: HashMap<String, usize>,
+    calls: Vec<u64>,
+    functions: Vec<Samples>,
+    lines: HashMap<u16, Samples>,
+    stack: Vec<usize>,
+    stacks: HashMap<Vec<usize>, u64>,
+}
+
+impl State {
+    fn function_index(&mut self, function: &Function) -> usize {
+        if let Some(index) = self.by_name.get(&function.name.value) {
+            return *index;
+        }
+
+        self.names.push(instrument::function_name(function));
+        self.calls.push(0);
+        self.functions.push(Samples::default());
+        self.by_name.insert(function.name.value.clone(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+/// Counts calls, instructions and time per function and per line.
+///
+/// Calls are always counted exactly. Instructions are sampled every
+/// `interval` instructions, each sample being charged the interval and the
+/// time since the previous sample; an interval of 1 measures every
+/// instruction. Clones share the same results.
+#[derive(Clone)]
+pub struct Profiler(Arc<Mutex<State>>);
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::sampling(1)
+    }
+
+    pub fn sampling(interval: u64) -> Profiler {
+        let interval = interval.max(1);
+        Profiler(Arc::new(Mutex::new(State { interval, countdown: interval, ..State::default() })))
+    }
+
+    pub fn profile(&self) -> Profile {
+        let state = self.0.lock().unwrap();
+
+        let mut functions: Vec<FunctionProfile> = state.names.iter().enumerate().map(|(i, name)| FunctionProfile {
+            name: name.clone(),
+            calls: state.calls[i],
+            instructions: state.functions[i].instructions,
+            time: state.functions[i].time,
+        }).collect();
+        functions.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.name.cmp(&b.name)));
+
+        let mut lines: Vec<LineProfile> = state.lines.iter().map(|(line, samples)| LineProfile {
+            line: *line,
+            instructions: samples.instructions,
+            time: samples.time,
+        }).collect();
+        lines.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.line.cmp(&b.line)));
+
+        let mut stacks: Vec<(Vec<String>, u64)> = state.stacks.iter().map(|(stack, count)| {
+            (stack.iter().map(|i| state.names[*i].clone()).collect(), *count)
+        }).collect();
+        stacks.sort();
+
+        Profile {
+            instructions: state.total.instructions,
+            time: state.total.time,
+            functions,
+            lines,
+            stacks,
+        }
+    }
+}
+
+impl Instrument for Profiler {
+    fn on_call(&mut self, function: &Function, depth: usize) {
+        let mut state = self.0.lock().unwrap();
+        let index = state.function_index(function);
+
+        state.calls[index] += 1;
+        state.stack.truncate(depth - 1);
+
+        // Time between two runs of the VM is not charged to either.
+        if depth == 1 {
+            state.last_sample = None;
+        }
+        state.stack.push(index);
+    }
+
+    fn on_instruction(&mut self, function: &Function, _offset: usize, line: u16, depth: usize, _stack: &[Value]) {
+        let mut state = self.0.lock().unwrap();
+
+        state.countdown -= 1;
+        if state.countdown > 0 {
+            return;
+        }
+        state.countdown = state.interval;
+
+        let now = Instant::now();
+        let elapsed = state.last_sample.map_or(Duration::ZERO, |last| now - last);
+        state.last_sample = Some(now);
+
+        let index = state.function_index(function);
+        let weight = state.interval;
+
+        // Keep the stack right even if frames were unwound by an error or
+        // the profiler was attached in the middle of a call.
+        state.stack.truncate(depth);
+        if state.stack.len() < depth {
+            state.stack.resize(depth - 1, index);
+            state.stack.push(index);
+        }
+
+        state.total.add(weight, elapsed);
+        state.functions[index].add(weight, elapsed);
+        state.lines.entry(line).or_default().add(weight, elapsed);
+
+        let State { stack, stacks, .. } = &mut *state;
+        match stacks.get_mut(stack) {
+            Some(count) => *count += weight,
+            None => { stacks.insert(stack.clone(), weight); },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    const FIB: &str = "\
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 2) + fib(n - 1);
+}
+print fib(10);
+";
+
+    fn profile(source: &str, profiler: Profiler) -> Profile {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.add_instrument(Box::new(profiler.clone()));
+        vm.interpret(source).unwrap();
+        profiler.profile()
+    }
+
+    #[test]
+    fn test_counts_calls_and_instructions() {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        let profile = profile(FIB, Profiler::new());
+
+        vm.interpret(FIB).unwrap();
+        assert_eq!(profile.instructions, vm.instruction_count());
+
+        let fib = profile.functions.iter().find(|f| f.name == "fib").unwrap();
+        let script = profile.functions.iter().find(|f| f.name == "<script>").unwrap();
+        assert_eq!(fib.calls, 177);
+        assert_eq!(script.calls, 1);
+        assert_eq!(fib.instructions + script.instructions, profile.instructions);
+        assert_eq!(profile.functions[0].name, "fib");
+
+        let lines: u64 = profile.lines.iter().map(|l| l.instructions).sum();
+        assert_eq!(lines, profile.instructions);
+        assert_eq!(profile.lines.iter().find(|l| l.line == 3).unwrap().instructions, 88 * 12);
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let profile = profile(FIB, Profiler::new());
+        let folded = profile.folded();
+
+        assert!(folded.lines().any(|l| l.starts_with("<script> ")));
+        assert!(folded.lines().any(|l| l.starts_with("<script>;fib ")));
+        assert!(folded.lines().any(|l| l.starts_with("<script>;fib;fib;fib;fib;fib;fib;fib;fib;fib ")));
+
+        let total: u64 = folded.lines().map(|l| l.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap()).sum();
+        assert_eq!(total, profile.instructions);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let exact = profile(FIB, Profiler::new());
+        let sampled = profile(FIB, Profiler::sampling(10));
+
+        assert_eq!(sampled.instructions, exact.instructions / 10 * 10);
+        assert_eq!(sampled.functions.iter().find(|f| f.name == "fib").unwrap().calls, 177);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = profile(FIB, Profiler::new()).report();
+
+        assert!(report.contains("     177"));
+        assert!(report.contains("fib\n"));
+        assert!(report.contains("<script>\n"));
+    }
+
+    #[test]
+    fn test_stack_recovers_after_error() {
+        let profiler = Profiler::new();
+        let mut vm = VM::new();
+        vm.add_instrument(Box::new(profiler.clone()));
+
+        assert!(vm.interpret("fun f() { return -nil; }\nf();").is_err());
+        vm.interpret("var x = 1;").unwrap();
+
+        let stacks: Vec<String> = profiler.profile().stacks.iter().map(|(s, _)| s.join(";")).collect();
+        assert_eq!(stacks, vec!["<script>", "<script>;f"]);
+    }
+}
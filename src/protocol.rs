@@ -0,0 +1,77 @@
+use std::io::{self, BufRead, Write};
+use serde_json::Value as Json;
+
+/// Reads one `Content-Length` framed JSON message, as used by the debug
+/// adapter and language server protocols. Returns `None` at end of input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') && name.eq_ignore_ascii_case("Content-Length") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Frames a sequence of messages, e.g. to script a client in tests.
+pub fn encode_messages(messages: &[Json]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for message in messages {
+        write_message(&mut buffer, message).unwrap();
+    }
+    buffer
+}
+
+/// Splits framed output back into messages.
+pub fn decode_messages(mut bytes: &[u8]) -> io::Result<Vec<Json>> {
+    let mut messages = Vec::new();
+    while let Some(message) = read_message(&mut bytes)? {
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = vec![json!({"seq": 1, "command": "initialize"}), json!({"text": "héllo\r\n"})];
+        let bytes = encode_messages(&messages);
+
+        assert!(bytes.starts_with(b"Content-Length: 32\r\n\r\n{\"command\""));
+        assert_eq!(decode_messages(&bytes).unwrap(), messages);
+    }
+
+    #[test]
+    fn test_missing_length() {
+        let mut input: &[u8] = b"Content-Type: json\r\n\r\n{}";
+        assert!(read_message(&mut input).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use rustyline::{completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context, Helper};
+use crate::{compiler::analyze, scanner::{Scanner, KEYWORDS}, token::TokenType};
+
+/// Whether `source` stops in the middle of a statement, so the REPL should
+/// read more lines before compiling it: a string is left open, a bracket
+/// is left unclosed, or the first compile error is at the end of the input.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0;
+
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+            TokenType::Error if token.lexeme == "Unterminated string." => return true,
+            TokenType::Eof => break,
+            _ => {},
+        }
+    }
+
+    if depth != 0 {
+        // Too many closing brackets cannot be fixed by reading more.
+        return depth > 0;
+    }
+
+    analyze(source).diagnostics.first().is_some_and(|d| d.offset == source.len())
+}
+
+/// Completions for the word before `pos` in `line`: the keywords and
+/// `globals` it starts, sorted, along with the byte offset of the word.
+pub fn complete(line: &str, pos: usize, globals: &[String]) -> (usize, Vec<String>) {
+    // Identifiers are ASCII, as in the scanner.
+    let start = line[..pos].char_indices().rev()
+        .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &line[start..pos];
+
+    if word.is_empty() {
+        return (pos, Vec::new());
+    }
+
+    let mut candidates: Vec<String> = KEYWORDS.iter().map(|(k, _)| k.to_string())
+        .chain(globals.iter().cloned())
+        .filter(|candidate| candidate.starts_with(word))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    (start, candidates)
+}
+
+pub const HELP: &str = "\
+:load FILE     run a script in the current session
+:reset         start over with a fresh VM
+:globals       list the globals defined so far with their values
+:dis CODE      show the bytecode CODE compiles to
+:time CODE     run CODE and report how long it took
+:trace on|off  print each instruction as it runs
+:help          show this list
+quit, exit     leave the REPL";
+
+/// A REPL meta-command, entered as a line starting with `:`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(String),
+    Reset,
+    Globals,
+    Dis(String),
+    Time(String),
+    Trace(bool),
+    Help,
+}
+
+impl Command {
+    /// Parses `line` if it is a meta-command, with an error message if it
+    /// is malformed.
+    pub fn parse(line: &str) -> Option<Result<Command, String>> {
+        let line = line.trim().strip_prefix(':')?;
+        let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        let command = match (name, argument) {
+            ("load", "") => Err("Usage: :load FILE".to_string()),
+            ("load", path) => Ok(Command::Load(path.to_string())),
+            ("reset", "") => Ok(Command::Reset),
+            ("globals", "") => Ok(Command::Globals),
+            ("dis", "") => Err("Usage: :dis CODE".to_string()),
+            ("dis", code) => Ok(Command::Dis(code.to_string())),
+            ("time", "") => Err("Usage: :time CODE".to_string()),
+            ("time", code) => Ok(Command::Time(code.to_string())),
+            ("trace", "on") => Ok(Command::Trace(true)),
+            ("trace", "off") => Ok(Command::Trace(false)),
+            ("trace", _) => Err("Usage: :trace on|off".to_string()),
+            ("help", "") => Ok(Command::Help),
+            ("reset" | "globals" | "help", _) => Err(format!(":{} takes no arguments", name)),
+            _ => Err(format!("Unknown command ':{}', see :help", name)),
+        };
+
+        Some(command)
+    }
+}
+
+/// The source `:dis` and `:time` run for `code`: an expression is printed,
+/// anything else is taken as statements, with the final `;` optional.
+pub fn as_statement(code: &str) -> String {
+    let code = code.trim().trim_end_matches(';');
+    let print = format!("print {};", code);
+
+    if analyze(&print).diagnostics.is_empty() {
+        print
+    } else if code.ends_with('}') {
+        code.to_string()
+    } else {
+        format!("{};", code)
+    }
+}
+
+/// Line editor support for the REPL, completing keywords and the globals
+/// defined so far.
+#[derive(Default)]
+pub struct LoxHelper {
+    pub globals: Vec<String>,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.globals))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_input() {
+        assert!(!is_incomplete("print 1;\n"));
+        assert!(!is_incomplete("fun f() {\n    return 1;\n}\n"));
+        assert!(!is_incomplete("\n"));
+    }
+
+    #[test]
+    fn test_unbalanced_brackets() {
+        assert!(is_incomplete("fun f() {\n"));
+        assert!(is_incomplete("{ { print 1; }\n"));
+        assert!(is_incomplete("print (1 +\n"));
+        assert!(!is_incomplete("print 1; }\n"));
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        assert!(is_incomplete("print \"first line\n"));
+        assert!(!is_incomplete("print \"first line\nsecond line\";\n"));
+    }
+
+    #[test]
+    fn test_complete() {
+        let globals = vec!["clock".to_string(), "counter".to_string(), "print_all".to_string()];
+
+        assert_eq!(complete("var x = co", 10, &globals), (8, vec!["counter".to_string()]));
+        assert_eq!(complete("pr", 2, &globals), (0, vec!["print".to_string(), "print_all".to_string()]));
+        assert_eq!(complete("f", 1, &globals), (0, vec!["false".to_string(), "for".to_string(), "fun".to_string()]));
+        assert_eq!(complete("print cl + 1;", 8, &globals), (6, vec!["class".to_string(), "clock".to_string()]));
+        assert_eq!(complete("print ", 6, &globals), (6, vec![]));
+    }
+
+    #[test]
+    fn test_complete_after_non_ascii() {
+        let globals = vec!["counter".to_string()];
+
+        assert_eq!(complete("print “co", 11, &globals), (9, vec!["counter".to_string()]));
+        assert_eq!(complete("print éco", 10, &globals), (8, vec!["counter".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("print 1;"), None);
+        assert_eq!(Command::parse(":load  tests/lox/functions/recursion.lox "), Some(Ok(Command::Load("tests/lox/functions/recursion.lox".to_string()))));
+        assert_eq!(Command::parse(":reset"), Some(Ok(Command::Reset)));
+        assert_eq!(Command::parse(":dis 1 + 2"), Some(Ok(Command::Dis("1 + 2".to_string()))));
+        assert_eq!(Command::parse(":trace off"), Some(Ok(Command::Trace(false))));
+    }
+
+    #[test]
+    fn test_malformed_commands() {
+        assert_eq!(Command::parse(":load"), Some(Err("Usage: :load FILE".to_string())));
+        assert_eq!(Command::parse(":trace maybe"), Some(Err("Usage: :trace on|off".to_string())));
+        assert_eq!(Command::parse(":globals x"), Some(Err(":globals takes no arguments".to_string())));
+        assert_eq!(Command::parse(":quit"), Some(Err("Unknown command ':quit', see :help".to_string())));
+    }
+
+    #[test]
+    fn test_as_statement() {
+        assert_eq!(as_statement("1 + 2"), "print 1 + 2;");
+        assert_eq!(as_statement("f(1);"), "print f(1);");
+        assert_eq!(as_statement("var x = 1"), "var x = 1;");
+        assert_eq!(as_statement("while (false) {}"), "while (false) {}");
+    }
+
+    #[test]
+    fn test_errors_at_end_of_input() {
+        assert!(is_incomplete("var x = 1\n"));
+        assert!(is_incomplete("print 1 +\n"));
+        assert!(!is_incomplete("print 1 + ;\n"));
+        assert!(!is_incomplete("print ; var x = 1\n"));
+    }
+}
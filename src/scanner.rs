@@ -2,6 +2,15 @@
 use crate::token::{Token, TokenType};
 use std::collections::HashMap;
 
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("and", TokenType::And), ("class", TokenType::Class), ("else", TokenType::Else),
+    ("false", TokenType::False), ("for", TokenType::For), ("fun", TokenType::Fun),
+    ("if", TokenType::If), ("nil", TokenType::Nil), ("or", TokenType::Or),
+    ("print", TokenType::Print), ("return", TokenType::Return), ("super", TokenType::Super),
+    ("this", TokenType::This), ("true", TokenType::True), ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
 pub struct Scanner<'a> {
     source: &'a str,
     start: usize,
@@ -20,33 +29,27 @@ pub enum ScanError {
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
-        let mut keywords = HashMap::new();
-        keywords.insert("and", TokenType::And);
-        keywords.insert("class", TokenType::Class);
-        keywords.insert("else", TokenType::Else);
-        keywords.insert("false", TokenType::False);
-        keywords.insert("for", TokenType::For);
-        keywords.insert("fun", TokenType::Fun);
-        keywords.insert("if", TokenType::If);
-        keywords.insert("nil", TokenType::Nil);
-        keywords.insert("or", TokenType::Or);
-        keywords.insert("print", TokenType::Print);
-        keywords.insert("return", TokenType::Return);
-        keywords.insert("super", TokenType::Super);
-        keywords.insert("this", TokenType::This);
-        keywords.insert("true", TokenType::True);
-        keywords.insert("var", TokenType::Var);
-        keywords.insert("while", TokenType::While);
-
         Self {
             source,
             start: 0,
             current: 0,
             line: 1,
-            keywords,
+            keywords: KEYWORDS.iter().copied().collect(),
         }
     }
 
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The next token, without consuming it.
+    pub fn peek_token(&mut self) -> Token<'a> {
+        let (start, current, line) = (self.start, self.current, self.line);
+        let token = self.scan_token();
+        (self.start, self.current, self.line) = (start, current, line);
+        token
+    }
+
     pub fn scan_token(&mut self) -> Token<'a> {
         self.skip_whitespace();
         self.start = self.current;
@@ -86,7 +89,13 @@ impl<'a> Scanner<'a> {
 
             b if is_alpha(b) => self.identifier(),
 
-            _ => self.scan_error("Unexpected character."),
+            _ => {
+                // Skip the rest of a multi-byte character.
+                while !self.source.is_char_boundary(self.current) {
+                    self.current += 1;
+                }
+                self.scan_error("Unexpected character.")
+            },
         }
     }
 
@@ -138,6 +147,7 @@ impl<'a> Scanner<'a> {
             token_type,
             lexeme,
             line: self.line,
+            offset: self.start,
         }
     }
 
@@ -146,6 +156,7 @@ impl<'a> Scanner<'a> {
             token_type: TokenType::Error,
             lexeme: message,
             line: self.line,
+            offset: self.start,
         }
     }
 
@@ -248,7 +259,7 @@ mod tests {
     use super::*;
     use crate::token::Token;
 
-    fn scan(source: &str) -> Vec<Token> {
+    fn scan(source: &str) -> Vec<Token<'_>> {
         let mut scanner = Scanner::new(source);
         let mut tokens = Vec::new();
         loop {
@@ -318,7 +329,14 @@ mod tests {
         assert_eq!(tokens[0].token_type, TokenType::Eof);
         assert_eq!(tokens[0].line, 4); // Line number should be 4
     }
-    
+
+    #[test]
+    fn test_offsets() {
+        let tokens = scan("var x\n  = 10;");
+        let offsets: Vec<usize> = tokens.iter().map(|t| t.offset).collect();
+        assert_eq!(offsets, vec![0, 4, 8, 10, 12, 13]);
+    }
+
     #[test]
     fn test_numbers() {
         let tokens = scan("123 45.67");
@@ -0,0 +1,533 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::{
+    block::Block, objects::{Function, LocalInfo, LoxString, NativeFunction}, op::OpCode, table::Table, value::Value
+};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SnapshotError {
+    #[error("Not a snapshot image")]
+    InvalidHeader,
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unexpected end of snapshot image")]
+    UnexpectedEnd,
+    #[error("Invalid tag {0} in snapshot image")]
+    InvalidTag(u8),
+    #[error("Invalid UTF-8 string in snapshot image")]
+    InvalidString,
+    #[error("Invalid function reference {0} in snapshot image")]
+    InvalidFunction(u32),
+    #[error("Invalid bytecode in function '{0}'")]
+    InvalidBytecode(String),
+    #[error("Native function '{0}' is not available in this VM")]
+    MissingNative(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ValueImage {
+    Number(f64),
+    Bool(bool),
+    Nil,
+    String(String),
+    Function(u32),
+    Native(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FunctionImage {
+    name: String,
+    arity: u32,
+    code: Vec<OpCode>,
+    lines: Vec<u16>,
+    constants: Vec<ValueImage>,
+    locals: Vec<LocalInfo>,
+}
+
+/// A serializable image of the globals and compiled functions of a `VM`.
+///
+/// Functions are stored once each, before any function that references
+/// them, so that sharing between globals survives a restore.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    functions: Vec<FunctionImage>,
+    globals: Vec<(String, ValueImage)>,
+}
+
+impl Snapshot {
+    const MAGIC: &'static [u8; 4] = b"LOXS";
+    const VERSION: u8 = 2;
+
+    /// Captures `globals`, skipping the natives the VM defines itself,
+    /// which a restoring VM recreates from its own capabilities.
+    pub fn capture(globals: &Table, is_builtin: impl Fn(&str, &NativeFunction) -> bool) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        let mut indices = HashMap::new();
+
+        for (name, value) in globals.iter() {
+            if let Value::NativeFunction(native) = &value && is_builtin(&name.value, native) {
+                continue;
+            }
+
+            let image = snapshot.value_image(&value, &mut indices);
+            snapshot.globals.push((name.value.clone(), image));
+        }
+
+        snapshot
+    }
+
+    fn value_image(&mut self, value: &Value, indices: &mut HashMap<*const Function, u32>) -> ValueImage {
+        match value {
+            Value::Number(n) => ValueImage::Number(*n),
+            Value::Bool(b) => ValueImage::Bool(*b),
+            Value::Nil => ValueImage::Nil,
+            Value::String(s) => ValueImage::String(s.value.clone()),
+            Value::Function(f) => ValueImage::Function(self.function_image(f, indices)),
+            Value::NativeFunction(native) => ValueImage::Native(native.name.to_string()),
+        }
+    }
+
+    fn function_image(&mut self, function: &Arc<Function>, indices: &mut HashMap<*const Function, u32>) -> u32 {
+        if let Some(index) = indices.get(&Arc::as_ptr(function)) {
+            return *index;
+        }
+
+        let constants = function.block.constants.iter()
+            .map(|constant| self.value_image(constant, indices))
+            .collect();
+
+        self.functions.push(FunctionImage {
+            name: function.name.value.clone(),
+            arity: function.arity as u32,
+            code: function.block.code.clone(),
+            lines: function.block.lines.clone(),
+            constants,
+            locals: function.locals.clone(),
+        });
+
+        let index = (self.functions.len() - 1) as u32;
+        indices.insert(Arc::as_ptr(function), index);
+        index
+    }
+
+    /// Rebuilds the globals of the image, resolving natives with `native`.
+    pub fn globals(&self, native: impl Fn(&str) -> Option<NativeFunction>) -> Result<Vec<(Arc<LoxString>, Value)>, SnapshotError> {
+        let mut functions: Vec<Arc<Function>> = Vec::with_capacity(self.functions.len());
+
+        for image in self.functions.iter() {
+            if !Self::verify(image) {
+                return Err(SnapshotError::InvalidBytecode(image.name.clone()));
+            }
+
+            let mut block = Block::new();
+            block.code = image.code.clone();
+            block.lines = image.lines.clone();
+
+            for constant in image.constants.iter() {
+                block.constants.push(Self::value(constant, &functions, &native)?);
+            }
+
+            functions.push(Arc::new(Function {
+                name: LoxString::new(&image.name),
+                block,
+                arity: image.arity as usize,
+                locals: image.locals.clone(),
+                tests: Vec::new(),
+            }));
+        }
+
+        self.globals.iter()
+            .map(|(name, value)| Ok((LoxString::new(name), Self::value(value, &functions, &native)?)))
+            .collect()
+    }
+
+    /// Checks that every operand stays inside its function, since the VM
+    /// trusts the compiler and does not bounds check instruction pointers.
+    fn verify(image: &FunctionImage) -> bool {
+        let len = image.code.len();
+
+        if image.lines.len() != len || image.code.last() != Some(&OpCode::Return) {
+            return false;
+        }
+
+        if image.locals.iter().any(|local| local.start > local.end || local.end > len) {
+            return false;
+        }
+
+        image.code.iter().enumerate().all(|(offset, op)| match *op {
+            OpCode::Constant(index) => (index as usize) < image.constants.len(),
+            OpCode::GetGlobal(index) | OpCode::DefGlobal(index) | OpCode::SetGlobal(index) => {
+                matches!(image.constants.get(index as usize), Some(ValueImage::String(_)))
+            },
+            OpCode::Jump(jump) | OpCode::JumpIfFalse(jump) => offset + 1 + (jump as usize) < len,
+            OpCode::Loop(jump) => offset >= jump as usize,
+            _ => true,
+        })
+    }
+
+    fn value(image: &ValueImage, functions: &[Arc<Function>], native: &impl Fn(&str) -> Option<NativeFunction>) -> Result<Value, SnapshotError> {
+        match image {
+            ValueImage::Number(n) => Ok(Value::Number(*n)),
+            ValueImage::Bool(b) => Ok(Value::Bool(*b)),
+            ValueImage::Nil => Ok(Value::Nil),
+            ValueImage::String(s) => Ok(Value::String(LoxString::new(s))),
+            ValueImage::Function(index) => functions.get(*index as usize)
+                .map(|f| Value::Function(f.clone()))
+                .ok_or(SnapshotError::InvalidFunction(*index)),
+            ValueImage::Native(name) => native(name)
+                .map(Value::NativeFunction)
+                .ok_or_else(|| SnapshotError::MissingNative(name.clone())),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+
+        writer.bytes.extend_from_slice(Self::MAGIC);
+        writer.u8(Self::VERSION);
+
+        writer.u32(self.functions.len() as u32);
+        for function in self.functions.iter() {
+            writer.string(&function.name);
+            writer.u32(function.arity);
+
+            writer.u32(function.code.len() as u32);
+            for (op, line) in function.code.iter().zip(function.lines.iter()) {
+                writer.op(*op);
+                writer.u16(*line);
+            }
+
+            writer.u32(function.constants.len() as u32);
+            for constant in function.constants.iter() {
+                writer.value(constant);
+            }
+
+            writer.u32(function.locals.len() as u32);
+            for local in function.locals.iter() {
+                writer.string(&local.name);
+                writer.u8(local.slot);
+                writer.u32(local.start as u32);
+                writer.u32(local.end as u32);
+            }
+        }
+
+        writer.u32(self.globals.len() as u32);
+        for (name, value) in self.globals.iter() {
+            writer.string(name);
+            writer.value(value);
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(Self::MAGIC.len())? != Self::MAGIC {
+            return Err(SnapshotError::InvalidHeader);
+        }
+
+        let version = reader.u8()?;
+        if version != Self::VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut snapshot = Snapshot::default();
+
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let arity = reader.u32()?;
+
+            let mut code = Vec::new();
+            let mut lines = Vec::new();
+            for _ in 0..reader.u32()? {
+                code.push(reader.op()?);
+                lines.push(reader.u16()?);
+            }
+
+            let mut constants = Vec::new();
+            for _ in 0..reader.u32()? {
+                constants.push(reader.value()?);
+            }
+
+            let mut locals = Vec::new();
+            for _ in 0..reader.u32()? {
+                locals.push(LocalInfo {
+                    name: reader.string()?,
+                    slot: reader.u8()?,
+                    start: reader.u32()? as usize,
+                    end: reader.u32()? as usize,
+                });
+            }
+
+            snapshot.functions.push(FunctionImage { name, arity, code, lines, constants, locals });
+        }
+
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let value = reader.value()?;
+            snapshot.globals.push((name, value));
+        }
+
+        Ok(snapshot)
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn value(&mut self, value: &ValueImage) {
+        match value {
+            ValueImage::Number(n) => {
+                self.u8(0);
+                self.bytes.extend_from_slice(&n.to_le_bytes());
+            },
+            ValueImage::Bool(b) => {
+                self.u8(1);
+                self.u8(*b as u8);
+            },
+            ValueImage::Nil => self.u8(2),
+            ValueImage::String(s) => {
+                self.u8(3);
+                self.string(s);
+            },
+            ValueImage::Function(index) => {
+                self.u8(4);
+                self.u32(*index);
+            },
+            ValueImage::Native(name) => {
+                self.u8(5);
+                self.string(name);
+            },
+        }
+    }
+
+    fn op(&mut self, op: OpCode) {
+        match op {
+            OpCode::Constant(index) => { self.u8(0); self.u8(index); },
+            OpCode::Nil => self.u8(1),
+            OpCode::True => self.u8(2),
+            OpCode::False => self.u8(3),
+            OpCode::Pop => self.u8(4),
+            OpCode::GetLocal(index) => { self.u8(5); self.u8(index); },
+            OpCode::SetLocal(index) => { self.u8(6); self.u8(index); },
+            OpCode::GetGlobal(index) => { self.u8(7); self.u8(index); },
+            OpCode::DefGlobal(index) => { self.u8(8); self.u8(index); },
+            OpCode::SetGlobal(index) => { self.u8(9); self.u8(index); },
+            OpCode::Equal => self.u8(10),
+            OpCode::Greater => self.u8(11),
+            OpCode::Less => self.u8(12),
+            OpCode::Add => self.u8(13),
+            OpCode::Subtract => self.u8(14),
+            OpCode::Multiply => self.u8(15),
+            OpCode::Divide => self.u8(16),
+            OpCode::Not => self.u8(17),
+            OpCode::Negate => self.u8(18),
+            OpCode::Print => self.u8(19),
+            OpCode::Jump(offset) => { self.u8(20); self.u16(offset); },
+            OpCode::JumpIfFalse(offset) => { self.u8(21); self.u16(offset); },
+            OpCode::Loop(offset) => { self.u8(22); self.u16(offset); },
+            OpCode::Call(arg_count) => { self.u8(23); self.u8(arg_count); },
+            OpCode::Return => self.u8(24),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.position.checked_add(len).ok_or(SnapshotError::UnexpectedEnd)?;
+        let bytes = self.bytes.get(self.position..end).ok_or(SnapshotError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| SnapshotError::InvalidString)
+    }
+
+    fn value(&mut self) -> Result<ValueImage, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(ValueImage::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))),
+            1 => Ok(ValueImage::Bool(self.u8()? != 0)),
+            2 => Ok(ValueImage::Nil),
+            3 => Ok(ValueImage::String(self.string()?)),
+            4 => Ok(ValueImage::Function(self.u32()?)),
+            5 => Ok(ValueImage::Native(self.string()?)),
+            tag => Err(SnapshotError::InvalidTag(tag)),
+        }
+    }
+
+    fn op(&mut self) -> Result<OpCode, SnapshotError> {
+        let op = match self.u8()? {
+            0 => OpCode::Constant(self.u8()?),
+            1 => OpCode::Nil,
+            2 => OpCode::True,
+            3 => OpCode::False,
+            4 => OpCode::Pop,
+            5 => OpCode::GetLocal(self.u8()?),
+            6 => OpCode::SetLocal(self.u8()?),
+            7 => OpCode::GetGlobal(self.u8()?),
+            8 => OpCode::DefGlobal(self.u8()?),
+            9 => OpCode::SetGlobal(self.u8()?),
+            10 => OpCode::Equal,
+            11 => OpCode::Greater,
+            12 => OpCode::Less,
+            13 => OpCode::Add,
+            14 => OpCode::Subtract,
+            15 => OpCode::Multiply,
+            16 => OpCode::Divide,
+            17 => OpCode::Not,
+            18 => OpCode::Negate,
+            19 => OpCode::Print,
+            20 => OpCode::Jump(self.u16()?),
+            21 => OpCode::JumpIfFalse(self.u16()?),
+            22 => OpCode::Loop(self.u16()?),
+            23 => OpCode::Call(self.u8()?),
+            24 => OpCode::Return,
+            tag => return Err(SnapshotError::InvalidTag(tag)),
+        };
+
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::Capabilities;
+    use crate::vm::VM;
+
+    const PRELUDE: &str = "
+        fun fib(n) {
+            if (n < 2) {
+                return n;
+            }
+
+            return fib(n - 2) + fib(n - 1);
+        }
+
+        fun greet(name) {
+            return \"hello \" + name;
+        }
+
+        var alias = fib;
+        var timer = clock;
+        var count = 10;
+        var done = false;
+    ";
+
+    const PROGRAM: &str = "
+        var result = fib(count);
+        var message = greet(\"world\");
+        done = alias == fib;
+        var elapsed = timer() >= 0;
+    ";
+
+    fn prelude_vm() -> VM {
+        let mut vm = VM::new();
+        vm.interpret(PRELUDE).unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let snapshot = prelude_vm().snapshot();
+        let restored = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+
+        assert_eq!(restored, snapshot);
+    }
+
+    #[test]
+    fn test_restored_vm_matches_original() {
+        let mut original = prelude_vm();
+        let bytes = original.snapshot().to_bytes();
+
+        let mut restored = VM::from_snapshot(&Snapshot::from_bytes(&bytes).unwrap()).unwrap();
+
+        original.interpret(PROGRAM).unwrap();
+        restored.interpret(PROGRAM).unwrap();
+
+        for name in ["result", "message", "done", "elapsed", "count"] {
+            assert_eq!(restored.get_global(name), original.get_global(name), "global '{}'", name);
+        }
+        assert_eq!(restored.get_global("result"), Some(Value::Number(55.0)));
+        assert_eq!(restored.get_global("done"), Some(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_restore_preserves_sharing() {
+        let vm = VM::from_snapshot(&prelude_vm().snapshot()).unwrap();
+
+        match (vm.get_global("fib"), vm.get_global("alias")) {
+            (Some(Value::Function(a)), Some(Value::Function(b))) => assert!(Arc::ptr_eq(&a, &b)),
+            _ => panic!("Expected functions"),
+        }
+    }
+
+    #[test]
+    fn test_restore_missing_native() {
+        let snapshot = prelude_vm().snapshot();
+        let mut vm = VM::with_capabilities(Capabilities::sandboxed());
+
+        assert_eq!(vm.restore(&snapshot), Err(SnapshotError::MissingNative("clock".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_images() {
+        let bytes = prelude_vm().snapshot().to_bytes();
+
+        assert_eq!(Snapshot::from_bytes(b"nope"), Err(SnapshotError::InvalidHeader));
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::UnexpectedEnd));
+
+        let mut version = bytes.clone();
+        version[4] = 99;
+        assert_eq!(Snapshot::from_bytes(&version), Err(SnapshotError::UnsupportedVersion(99)));
+    }
+
+    #[test]
+    fn test_invalid_bytecode() {
+        let mut snapshot = prelude_vm().snapshot();
+        snapshot.functions[0].code[0] = OpCode::Jump(u16::MAX);
+
+        let mut vm = VM::new();
+        assert!(matches!(vm.restore(&snapshot), Err(SnapshotError::InvalidBytecode(_))));
+    }
+}
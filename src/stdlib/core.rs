@@ -0,0 +1,189 @@
+use crate::{debugger::describe, error::LoxError, objects::{LoxString, NativeFunction}, value::Value, vm::VM};
+use super::type_error;
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::variadic("assert", 1, 2, assert),
+    NativeFunction::variadic("assert_eq", 2, 3, assert_eq),
+    NativeFunction::variadic("assert_throws", 1, 2, assert_throws),
+    NativeFunction::new("type", 1, type_of),
+    NativeFunction::new("str", 1, str),
+    NativeFunction::new("num", 1, num),
+    NativeFunction::new("bool", 1, bool),
+    NativeFunction::new("is_callable", 1, is_callable),
+    NativeFunction::new("arity", 1, arity),
+];
+
+/// An assertion error, prefixed by the optional message argument at `index`.
+fn failure(args: &[Value], index: usize, detail: &str) -> LoxError {
+    let msg = match (args.get(index), detail.is_empty()) {
+        (Some(msg), true) => format!("Assertion failed: {}", msg),
+        (Some(msg), false) => format!("Assertion failed: {}: {}", msg, detail),
+        (None, true) => "Assertion failed".to_string(),
+        (None, false) => format!("Assertion failed: {}", detail),
+    };
+    LoxError::RuntimeError(msg)
+}
+
+/// `assert(condition[, message])`
+fn assert(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    if args[0].is_truthy() {
+        Ok(Value::Nil)
+    } else {
+        Err(failure(args, 1, ""))
+    }
+}
+
+/// `assert_eq(actual, expected[, message])`
+fn assert_eq(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    if args[0] == args[1] {
+        Ok(Value::Nil)
+    } else {
+        Err(failure(args, 2, &format!("expected {} but got {}", describe(&args[1]), describe(&args[0]))))
+    }
+}
+
+/// `assert_throws(function[, message])` calls `function` and returns the
+/// message of the runtime error it raises.
+fn assert_throws(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    // `args` is on the stack the call runs on.
+    let args = args.to_vec();
+
+    match vm.call_function(args[0].clone(), &[]) {
+        Err(LoxError::RuntimeError(msg)) => Ok(Value::String(LoxString::new(&msg))),
+        Err(e) => Err(e),
+        Ok(_) => Err(failure(&args, 1, "expected an error")),
+    }
+}
+
+/// `type(value)` is the name of the value's type, such as `"number"`.
+fn type_of(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::String(LoxString::new(args[0].type_name())))
+}
+
+/// `str(value)` is the value as `print` shows it.
+fn str(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::String(LoxString::new(&args[0].to_string())))
+}
+
+/// Whether `s` is written like a number literal, optionally negated.
+fn is_number_literal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    all_digits(whole) && all_digits(fraction)
+}
+
+/// `num(value)` parses a string written like a number literal, surrounding
+/// whitespace aside.
+fn num(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    match &args[0] {
+        Value::Number(n) => Ok(Value::Number(*n)),
+        Value::String(s) if is_number_literal(s.value.trim()) => {
+            Ok(Value::Number(s.value.trim().parse().unwrap()))
+        },
+        Value::String(_) => Err(LoxError::RuntimeError(format!("Cannot convert {} to a number", describe(&args[0])))),
+        _ => Err(type_error(args, 0, "a number or a string")),
+    }
+}
+
+/// `bool(value)` is whether the value counts as true in a condition.
+fn bool(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(args[0].is_truthy()))
+}
+
+fn is_callable(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(matches!(args[0], Value::Function(_) | Value::NativeFunction(_))))
+}
+
+/// `arity(function)` is how many arguments the function requires. Some
+/// natives take further optional arguments.
+fn arity(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    match &args[0] {
+        Value::Function(f) => Ok(Value::Number(f.arity as f64)),
+        Value::NativeFunction(nf) => Ok(Value::Number(nf.arity.0 as f64)),
+        _ => Err(type_error(args, 0, "a function")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::LoxError, vm::VM};
+
+    fn run(source: &str) -> Result<(), LoxError> {
+        let mut vm = VM::new();
+        vm.set_output(Box::new(std::io::sink()));
+        vm.interpret(source)
+    }
+
+    fn error(source: &str) -> String {
+        match run(source) {
+            Err(LoxError::RuntimeError(msg)) => msg,
+            result => panic!("expected a runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_assert() {
+        run("assert(true); assert(1 < 2, \"math\");").unwrap();
+
+        assert_eq!(error("assert(false);"), "Assertion failed");
+        assert_eq!(error("assert(nil, \"no value\");"), "Assertion failed: no value");
+        assert_eq!(error("assert();"), "Expected 1 to 2 arguments but got 0");
+    }
+
+    #[test]
+    fn test_assert_eq() {
+        run("assert_eq(1 + 2, 3); assert_eq(\"a\" + \"b\", \"ab\"); assert_eq(nil, nil);").unwrap();
+
+        assert_eq!(error("assert_eq(1, 2);"), "Assertion failed: expected 2 but got 1");
+        assert_eq!(error("assert_eq(\"a\", \"b\", \"letters\");"), "Assertion failed: letters: expected \"b\" but got \"a\"");
+    }
+
+    #[test]
+    fn test_assert_throws() {
+        run("fun bad() { return -nil; }\nassert_eq(assert_throws(bad), \"Operand must be a number\");").unwrap();
+
+        assert_eq!(error("fun fine() {}\nassert_throws(fine);"), "Assertion failed: expected an error");
+        assert_eq!(error("fun fine() {}\nassert_throws(fine, \"fine\");"), "Assertion failed: fine: expected an error");
+    }
+
+    #[test]
+    fn test_type_and_str() {
+        run("assert_eq(type(1), \"number\"); assert_eq(type(\"s\"), \"string\"); assert_eq(type(nil), \"nil\");").unwrap();
+        run("fun f() {}\nassert_eq(type(true), \"bool\"); assert_eq(type(f), \"function\"); assert_eq(type(clock), \"function\");").unwrap();
+        run("fun f() {}\nassert_eq(str(1.5), \"1.5\"); assert_eq(str(nil), \"nil\"); assert_eq(str(f), \"<fn f>\"); assert_eq(str(\"s\"), \"s\");").unwrap();
+    }
+
+    #[test]
+    fn test_num() {
+        run("assert_eq(num(\" 42 \"), 42); assert_eq(num(\"-0.5\"), -0.5); assert_eq(num(7), 7);").unwrap();
+
+        assert_eq!(error("num(\"12abc\");"), "Cannot convert \"12abc\" to a number");
+        assert_eq!(error("num(\"\");"), "Cannot convert \"\" to a number");
+        for input in ["inf", "infinity", "NaN", "+5", "1e3", "1.", ".5", "1.2.3", "- 1"] {
+            assert_eq!(error(&format!("num(\"{}\");", input)), format!("Cannot convert \"{}\" to a number", input));
+        }
+        assert_eq!(error("num(true);"), "Argument 1 must be a number or a string but got bool");
+    }
+
+    #[test]
+    fn test_bool_and_callables() {
+        run("assert_eq(bool(true), true); assert_eq(bool(nil), false); assert_eq(bool(bool(false)), false);").unwrap();
+        run("fun f() {}\nassert(is_callable(f)); assert(is_callable(clock)); assert_eq(is_callable(\"f\"), false);").unwrap();
+    }
+
+    #[test]
+    fn test_arity() {
+        run("fun f(a, b, c) {}\nassert_eq(arity(f), 3); assert_eq(arity(clock), 0); assert_eq(arity(assert), 1);").unwrap();
+
+        assert_eq!(error("arity(1);"), "Argument 1 must be a function but got number");
+        assert_eq!(error("type();"), "Expected 1 arguments but got 0");
+    }
+
+    #[test]
+    fn test_assert_throws_keeps_the_stack() {
+        // The caller's locals survive the failed call.
+        run("fun bad() { return bad(1); }\n{ var a = 1; var msg = assert_throws(bad); assert_eq(a, 1); assert_eq(msg, \"Expected 0 arguments but got 1\"); }").unwrap();
+    }
+}
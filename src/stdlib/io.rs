@@ -0,0 +1,331 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use crate::{error::LoxError, memory, objects::{LoxString, NativeFunction}, value::Value, vm::VM};
+use super::{count_or_part, string};
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::new("read_file", 1, read_file),
+    NativeFunction::new("write_file", 2, write_file),
+    NativeFunction::new("append_file", 2, append_file),
+    NativeFunction::variadic("read_lines", 1, 2, read_lines),
+    NativeFunction::new("exists", 1, exists),
+    NativeFunction::new("remove", 1, remove),
+    NativeFunction::variadic("list_dir", 1, 2, list_dir),
+    NativeFunction::new("read_line", 0, read_line),
+    NativeFunction::new("read_all", 0, read_all),
+    NativeFunction::variadic("input", 0, 1, input),
+];
+
+fn io_error(action: &str, path: &str, e: io::Error) -> LoxError {
+    LoxError::RuntimeError(format!("Could not {} {}: {}", action, path, e))
+}
+
+fn read(vm: &mut VM, path: &str) -> Result<String, LoxError> {
+    // Check the memory limit before reading the whole file in.
+    if let Ok(metadata) = fs::metadata(path) {
+        vm.reserve(memory::string_size(metadata.len() as usize))?;
+    }
+    fs::read_to_string(path).map_err(|e| io_error("read", path, e))
+}
+
+fn read_file(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let text = read(vm, string(args, 0)?)?;
+    Ok(Value::String(LoxString::new(&text)))
+}
+
+/// `write_file(path, text)` replaces the file's contents, creating it if
+/// needed.
+fn write_file(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let path = string(args, 0)?;
+    fs::write(path, string(args, 1)?).map_err(|e| io_error("write", path, e))?;
+    Ok(Value::Nil)
+}
+
+fn append_file(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let path = string(args, 0)?;
+    let text = string(args, 1)?;
+
+    OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|e| io_error("append to", path, e))?;
+    Ok(Value::Nil)
+}
+
+/// `read_lines(path)` counts the file's lines and `read_lines(path, index)`
+/// returns one of them without its line ending, or nil past the last.
+fn read_lines(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let text = read(vm, string(args, 0)?)?;
+    count_or_part(args, 1, text.lines())
+}
+
+fn exists(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(fs::exists(string(args, 0)?).unwrap_or(false)))
+}
+
+/// `remove(path)` deletes a file or an empty directory.
+fn remove(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let path = string(args, 0)?;
+
+    let result = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+        _ => fs::remove_file(path),
+    };
+    result.map_err(|e| io_error("remove", path, e))?;
+    Ok(Value::Nil)
+}
+
+/// `list_dir(path)` counts the entries of a directory and
+/// `list_dir(path, index)` returns one of their names, in sorted order.
+fn list_dir(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let path = string(args, 0)?;
+
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned())).collect::<io::Result<Vec<_>>>())
+        .map_err(|e| io_error("list", path, e))?;
+    names.sort();
+
+    count_or_part(args, 1, names.iter().map(String::as_str))
+}
+
+fn input_error(e: io::Error) -> LoxError {
+    LoxError::RuntimeError(format!("Could not read input: {}", e))
+}
+
+/// Moves the next chunk of `input` into `bytes`, returning its length. With
+/// `line`, the chunk ends after the first newline.
+fn read_chunk(input: &mut dyn BufRead, bytes: &mut Vec<u8>, line: bool) -> io::Result<usize> {
+    let chunk = input.fill_buf()?;
+    let len = match chunk.iter().position(|b| *b == b'\n') {
+        Some(newline) if line => newline + 1,
+        _ => chunk.len(),
+    };
+    bytes.extend_from_slice(&chunk[..len]);
+    input.consume(len);
+    Ok(len)
+}
+
+/// The rest of the input, or with `line` the rest of the current line.
+fn read_input(vm: &mut VM, line: bool) -> Result<String, LoxError> {
+    let mut bytes = Vec::new();
+
+    // Reading chunk by chunk checks the memory limit as the text grows.
+    loop {
+        match vm.with_input(|input| read_chunk(input, &mut bytes, line)) {
+            Ok(0) => break,
+            Ok(_) => vm.reserve(memory::string_size(bytes.len()))?,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(input_error(e)),
+        }
+        if line && bytes.ends_with(b"\n") {
+            break;
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|e| input_error(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// The next line of input without its line ending, or nil at the end.
+fn next_line(vm: &mut VM) -> Result<Value, LoxError> {
+    let line = read_input(vm, true)?;
+    if line.is_empty() {
+        return Ok(Value::Nil);
+    }
+
+    let line = line.strip_suffix('\n').map_or(line.as_str(), |line| line.strip_suffix('\r').unwrap_or(line));
+    Ok(Value::String(LoxString::new(line)))
+}
+
+/// `read_line()` reads a line from stdin, returning nil at the end of it.
+fn read_line(vm: &mut VM, _args: &[Value]) -> Result<Value, LoxError> {
+    next_line(vm)
+}
+
+/// `read_all()` reads the rest of stdin.
+fn read_all(vm: &mut VM, _args: &[Value]) -> Result<Value, LoxError> {
+    let text = read_input(vm, false)?;
+    Ok(Value::String(LoxString::new(&text)))
+}
+
+/// `input([prompt])` prints the prompt without a newline, then reads a line
+/// like `read_line`.
+fn input(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    if !args.is_empty() {
+        let prompt = string(args, 0)?;
+        let output = vm.output();
+        output.write_all(prompt.as_bytes()).and_then(|_| output.flush())
+            .map_err(|e| LoxError::RuntimeError(format!("Could not write output: {}", e)))?;
+    }
+
+    next_line(vm)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, Cursor, Read};
+    use std::path::PathBuf;
+    use crate::{error::LoxError, stdlib::{Capabilities, Module}, testing::Capture, vm::VM};
+
+    /// A fresh directory for one test, removed when it is dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("lox-io-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).display().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn run(source: &str) -> Result<(), LoxError> {
+        VM::new().interpret(source)
+    }
+
+    /// Runs `source` with `input` as its stdin, returning what it printed.
+    fn run_with_input(source: &str, input: &str) -> String {
+        let output = Capture::default();
+        let mut vm = VM::new();
+        vm.set_input(Box::new(Cursor::new(input.to_string())));
+        vm.set_output(Box::new(output.clone()));
+
+        vm.interpret(source).unwrap();
+        output.text()
+    }
+
+    #[test]
+    fn test_write_append_and_read() {
+        let dir = TempDir::new("read");
+        let path = dir.path("data.txt");
+
+        run(&format!("
+            var path = \"{}\";
+            write_file(path, \"first\");
+            append_file(path, \" line\nsecond line\r\nthird\");
+            assert_eq(read_lines(path), 3);
+            assert_eq(read_lines(path, 1), \"second line\");
+            assert_eq(read_lines(path, 3), nil);
+            write_file(path, \"replaced\");
+            assert_eq(read_file(path), \"replaced\");
+        ", path)).unwrap();
+
+        run(&format!("append_file(\"{}\", \"new\");", dir.path("appended.txt"))).unwrap();
+        assert_eq!(fs::read_to_string(dir.path("appended.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_read_lines_rereads_changed_files() {
+        let dir = TempDir::new("reread");
+        let path = dir.path("data.txt");
+        fs::write(&path, "a\nb\n").unwrap();
+
+        let mut vm = VM::new();
+        vm.interpret(&format!("
+            var path = \"{}\";
+            assert_eq(read_lines(path), 2);
+            write_file(path, \"c\");
+            assert_eq(read_lines(path), 1);
+            assert_eq(read_lines(path, 0), \"c\");
+        ", path)).unwrap();
+
+        fs::write(&path, "d\ne\nf").unwrap();
+        vm.interpret("assert_eq(read_lines(path, 2), \"f\");").unwrap();
+    }
+
+    #[test]
+    fn test_directories() {
+        let dir = TempDir::new("dirs");
+        fs::write(dir.path("b.lox"), "").unwrap();
+        fs::write(dir.path("a.lox"), "").unwrap();
+        fs::create_dir(dir.path("empty")).unwrap();
+
+        run(&format!("
+            var dir = \"{}\";
+            assert_eq(list_dir(dir), 3);
+            assert_eq(list_dir(dir, 0), \"a.lox\");
+            assert_eq(list_dir(dir, 2), \"empty\");
+            assert(exists(\"{}\"));
+            remove(\"{}\");
+            remove(\"{}\");
+            assert_eq(list_dir(dir), 1);
+            assert_eq(exists(\"{}\"), false);
+        ", dir.path(""), dir.path("a.lox"), dir.path("a.lox"), dir.path("empty"), dir.path("a.lox"))).unwrap();
+    }
+
+    #[test]
+    fn test_io_errors() {
+        let dir = TempDir::new("errors");
+        let missing = dir.path("missing.txt");
+
+        let error = |source: String| match run(&source) {
+            Err(LoxError::RuntimeError(msg)) => msg,
+            result => panic!("expected a runtime error, got {:?}", result),
+        };
+
+        assert!(error(format!("read_file(\"{}\");", missing)).starts_with(&format!("Could not read {}: ", missing)));
+        assert!(error(format!("remove(\"{}\");", missing)).starts_with(&format!("Could not remove {}: ", missing)));
+        assert!(error(format!("list_dir(\"{}\");", missing)).starts_with(&format!("Could not list {}: ", missing)));
+        assert!(error(format!("write_file(\"{}\", \"x\");", dir.path("no/such/dir.txt"))).starts_with("Could not write "));
+        assert_eq!(error("write_file(\"out.txt\", 42);".to_string()), "Argument 2 must be a string but got number");
+    }
+
+    #[test]
+    fn test_read_line() {
+        let source = "var line = read_line();\nwhile (line != nil) { print \"[\" + line + \"]\"; line = read_line(); }";
+
+        assert_eq!(run_with_input(source, "one\n\nthree\r\nfour"), "[one]\n[]\n[three]\n[four]\n");
+        assert_eq!(run_with_input(source, ""), "");
+    }
+
+    #[test]
+    fn test_read_all_and_input() {
+        assert_eq!(run_with_input("print read_line(); print read_all(); print read_all() == \"\";", "first\nsecond\nthird\n"), "first\nsecond\nthird\n\ntrue\n");
+        assert_eq!(run_with_input("var name = input(\"Name: \"); print \"Hello, \" + name;", "Ada\n"), "Name: Hello, Ada\n");
+        assert_eq!(run_with_input("print input(); print input(\"> \");", "x\n"), "x\n> nil\n");
+    }
+
+    #[test]
+    fn test_read_all_respects_memory_limit() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(1024 * 1024));
+        vm.set_input(Box::new(Cursor::new("x".repeat(2 * 1024 * 1024))));
+
+        assert!(matches!(vm.interpret("read_all();"), Err(LoxError::OutOfMemory(_))));
+    }
+
+    #[test]
+    fn test_read_line_respects_memory_limit() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(1024 * 1024));
+        // A line that never ends.
+        vm.set_input(Box::new(io::BufReader::new(Cursor::new("short\n").chain(io::repeat(b'x')))));
+
+        vm.interpret("assert_eq(read_line(), \"short\");").unwrap();
+        assert!(matches!(vm.interpret("read_line();"), Err(LoxError::OutOfMemory(_))));
+    }
+
+    #[test]
+    fn test_invalid_utf8_input() {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(Cursor::new(vec![b'a', 0xff])));
+
+        assert!(matches!(vm.interpret("read_all();"), Err(LoxError::RuntimeError(msg)) if msg.starts_with("Could not read input: ")));
+    }
+
+    #[test]
+    fn test_io_capability() {
+        let mut vm = VM::with_capabilities(Capabilities::all().without(Module::Io));
+        assert!(matches!(vm.interpret("exists(\"x\");"), Err(LoxError::RuntimeError(msg)) if msg == "Undefined variable 'exists'"));
+        assert!(matches!(VM::sandboxed().interpret("read_file(\"x\");"), Err(LoxError::RuntimeError(_))));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{error::LoxError, objects::NativeFunction, value::Value, vm::VM};
+use super::number;
+
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("PI", std::f64::consts::PI),
+    ("E", std::f64::consts::E),
+];
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::new("abs", 1, abs),
+    NativeFunction::new("sqrt", 1, sqrt),
+    NativeFunction::new("pow", 2, pow),
+    NativeFunction::new("exp", 1, exp),
+    NativeFunction::new("log", 1, log),
+    NativeFunction::new("log2", 1, log2),
+    NativeFunction::new("log10", 1, log10),
+    NativeFunction::new("floor", 1, floor),
+    NativeFunction::new("ceil", 1, ceil),
+    NativeFunction::new("round", 1, round),
+    NativeFunction::new("min", 2, min),
+    NativeFunction::new("max", 2, max),
+    NativeFunction::new("div", 2, div),
+    NativeFunction::new("mod", 2, modulo),
+    NativeFunction::new("sin", 1, sin),
+    NativeFunction::new("cos", 1, cos),
+    NativeFunction::new("tan", 1, tan),
+    NativeFunction::new("asin", 1, asin),
+    NativeFunction::new("acos", 1, acos),
+    NativeFunction::new("atan", 1, atan),
+    NativeFunction::new("atan2", 2, atan2),
+];
+
+fn unary(args: &[Value], f: fn(f64) -> f64) -> Result<Value, LoxError> {
+    Ok(Value::Number(f(number(args, 0)?)))
+}
+
+fn binary(args: &[Value], f: fn(f64, f64) -> f64) -> Result<Value, LoxError> {
+    Ok(Value::Number(f(number(args, 0)?, number(args, 1)?)))
+}
+
+fn abs(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::abs)
+}
+
+fn sqrt(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::sqrt)
+}
+
+fn pow(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    binary(args, f64::powf)
+}
+
+fn exp(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::exp)
+}
+
+/// `log(x)` is the natural logarithm.
+fn log(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::ln)
+}
+
+fn log2(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::log2)
+}
+
+fn log10(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::log10)
+}
+
+fn floor(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::floor)
+}
+
+fn ceil(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::ceil)
+}
+
+/// `round(x)` rounds halfway cases away from zero.
+fn round(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::round)
+}
+
+fn min(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    binary(args, f64::min)
+}
+
+fn max(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    binary(args, f64::max)
+}
+
+fn divisor(args: &[Value]) -> Result<f64, LoxError> {
+    match number(args, 1)? {
+        0.0 => Err(LoxError::RuntimeError("Division by zero".to_string())),
+        b => Ok(b),
+    }
+}
+
+/// `div(a, b)` divides and rounds down, so `div(-7, 2)` is -4.
+fn div(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let a = number(args, 0)?;
+    let b = divisor(args)?;
+
+    Ok(Value::Number((a / b).floor()))
+}
+
+/// `mod(a, b)` is the remainder of `div(a, b)`, with the sign of `b`.
+fn modulo(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let a = number(args, 0)?;
+    let b = divisor(args)?;
+
+    let r = a % b;
+    Ok(Value::Number(if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }))
+}
+
+fn sin(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::sin)
+}
+
+fn cos(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::cos)
+}
+
+fn tan(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::tan)
+}
+
+fn asin(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::asin)
+}
+
+fn acos(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::acos)
+}
+
+fn atan(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    unary(args, f64::atan)
+}
+
+/// `atan2(y, x)` is the angle of the point `(x, y)`.
+fn atan2(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    binary(args, f64::atan2)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::LoxError, value::Value, vm::VM};
+
+    fn eval(expression: &str) -> Result<Value, LoxError> {
+        let mut vm = VM::sandboxed();
+        vm.interpret(&format!("var result = {};", expression))?;
+        Ok(vm.get_global("result").unwrap())
+    }
+
+    fn number(expression: &str) -> f64 {
+        match eval(expression) {
+            Ok(Value::Number(n)) => n,
+            result => panic!("expected a number from {}, got {:?}", expression, result),
+        }
+    }
+
+    fn error(expression: &str) -> String {
+        match eval(expression) {
+            Err(LoxError::RuntimeError(msg)) => msg,
+            result => panic!("expected a runtime error from {}, got {:?}", expression, result),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(number("sqrt(16)"), 4.0);
+        assert_eq!(number("pow(2, 10)"), 1024.0);
+        assert_eq!(number("abs(-3)"), 3.0);
+        assert_eq!(number("min(3, -1)"), -1.0);
+        assert_eq!(number("max(3, -1)"), 3.0);
+        assert_eq!(number("exp(0) + log(E) + log2(8) + log10(1000)"), 8.0);
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(number("floor(-1.5)"), -2.0);
+        assert_eq!(number("ceil(-1.5)"), -1.0);
+        assert_eq!(number("round(2.5)"), 3.0);
+        assert_eq!(number("round(-2.5)"), -3.0);
+    }
+
+    #[test]
+    fn test_integer_division() {
+        assert_eq!(number("div(7, 2)"), 3.0);
+        assert_eq!(number("div(-7, 2)"), -4.0);
+        assert_eq!(number("mod(7, 3)"), 1.0);
+        assert_eq!(number("mod(-7, 3)"), 2.0);
+        assert_eq!(number("mod(7, -3)"), -2.0);
+        assert_eq!(error("div(1, 0)"), "Division by zero");
+        assert_eq!(error("mod(1, 0)"), "Division by zero");
+    }
+
+    #[test]
+    fn test_trigonometry() {
+        assert_eq!(number("sin(0)"), 0.0);
+        assert_eq!(number("cos(PI)"), -1.0);
+        assert!((number("tan(PI / 4)") - 1.0).abs() < 1e-12);
+        assert_eq!(number("asin(1)"), std::f64::consts::FRAC_PI_2);
+        assert_eq!(number("acos(1)"), 0.0);
+        assert_eq!(number("atan(1) * 4"), std::f64::consts::PI);
+        assert_eq!(number("atan2(1, -1)"), 3.0 * std::f64::consts::FRAC_PI_4);
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        assert_eq!(error("sqrt(\"4\")"), "Argument 1 must be a number but got string");
+        assert_eq!(error("pow(2, nil)"), "Argument 2 must be a number but got nil");
+        assert_eq!(error("floor()"), "Expected 1 arguments but got 0");
+        assert_eq!(error("max(1, 2, 3)"), "Expected 2 arguments but got 3");
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{error::LoxError, objects::{LoxString, NativeFunction}, value::Value};
+
+mod core;
+mod io;
+mod math;
+mod os;
+mod string;
+mod time;
+
+/// A group of native functions that can be exposed to scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Module {
+    Core,
+    Math,
+    String,
+    Io,
+    Os,
+    Time,
+}
+
+impl Module {
+    pub const ALL: [Module; 6] = [Module::Core, Module::Math, Module::String, Module::Io, Module::Os, Module::Time];
+
+    pub fn natives(self) -> &'static [NativeFunction] {
+        match self {
+            Module::Core => core::NATIVES,
+            Module::Math => math::NATIVES,
+            Module::String => string::NATIVES,
+            Module::Io => io::NATIVES,
+            Module::Os => os::NATIVES,
+            Module::Time => time::NATIVES,
+        }
+    }
+
+    /// Numbers the module defines as globals next to its natives.
+    pub fn constants(self) -> &'static [(&'static str, f64)] {
+        match self {
+            Module::Math => math::CONSTANTS,
+            _ => &[],
+        }
+    }
+}
+
+fn type_error(args: &[Value], index: usize, expected: &str) -> LoxError {
+    LoxError::RuntimeError(format!("Argument {} must be {} but got {}", index + 1, expected, args[index].type_name()))
+}
+
+/// The number argument at `index`, failing with the type it got otherwise.
+fn number(args: &[Value], index: usize) -> Result<f64, LoxError> {
+    match &args[index] {
+        Value::Number(n) => Ok(*n),
+        _ => Err(type_error(args, index, "a number")),
+    }
+}
+
+/// The argument at `index` as a position or count, which must be a
+/// non-negative integer.
+fn integer(args: &[Value], index: usize) -> Result<usize, LoxError> {
+    match number(args, index)? {
+        n if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+        n => Err(LoxError::RuntimeError(format!("Argument {} must be a non-negative integer but got {}", index + 1, n))),
+    }
+}
+
+fn string(args: &[Value], index: usize) -> Result<&str, LoxError> {
+    match &args[index] {
+        Value::String(s) => Ok(&s.value),
+        _ => Err(type_error(args, index, "a string")),
+    }
+}
+
+/// Lox has no lists, so natives producing several strings return how many
+/// there are unless given the index of one, at `index` in `args`. Only the
+/// part asked for is copied out of `parts`.
+fn count_or_part<'a>(args: &[Value], index: usize, mut parts: impl Iterator<Item = &'a str>) -> Result<Value, LoxError> {
+    if args.len() <= index {
+        return Ok(Value::Number(parts.count() as f64));
+    }
+    Ok(parts.nth(integer(args, index)?).map_or(Value::Nil, |part| Value::String(LoxString::new(part))))
+}
+
+/// The set of modules a `VM` defines as globals when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub const fn none() -> Capabilities {
+        Capabilities(0)
+    }
+
+    pub const fn all() -> Capabilities {
+        Capabilities(0).with(Module::Core).with(Module::Math).with(Module::String).with(Module::Io).with(Module::Os).with(Module::Time)
+    }
+
+    /// Only the modules whose natives are pure and cannot observe the host.
+    pub const fn sandboxed() -> Capabilities {
+        Capabilities(0).with(Module::Core).with(Module::Math).with(Module::String)
+    }
+
+    pub const fn with(self, module: Module) -> Capabilities {
+        Capabilities(self.0 | Self::bit(module))
+    }
+
+    pub const fn without(self, module: Module) -> Capabilities {
+        Capabilities(self.0 & !Self::bit(module))
+    }
+
+    pub const fn contains(self, module: Module) -> bool {
+        self.0 & Self::bit(module) != 0
+    }
+
+    pub fn modules(self) -> impl Iterator<Item = Module> {
+        Module::ALL.into_iter().filter(move |module| self.contains(*module))
+    }
+
+    const fn bit(module: Module) -> u8 {
+        1 << module as u8
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::none().with(Module::Math).with(Module::Time);
+
+        assert!(caps.contains(Module::Math));
+        assert!(caps.contains(Module::Time));
+        assert!(!caps.contains(Module::Io));
+        assert_eq!(caps.modules().collect::<Vec<_>>(), vec![Module::Math, Module::Time]);
+
+        assert!(!caps.without(Module::Time).contains(Module::Time));
+        assert_eq!(Capabilities::all().modules().count(), Module::ALL.len());
+        assert!(!Capabilities::sandboxed().contains(Module::Io));
+        assert!(!Capabilities::sandboxed().contains(Module::Os));
+        assert!(!Capabilities::sandboxed().contains(Module::Time));
+    }
+}
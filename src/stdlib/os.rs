@@ -0,0 +1,55 @@
+use crate::{error::LoxError, objects::{LoxString, NativeFunction}, value::Value, vm::VM};
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::variadic("args", 0, 1, args),
+];
+
+/// `args()` returns how many arguments were passed to the script and
+/// `args(index)` the argument at `index`, or nil past the last one.
+fn args(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let Some(index) = args.first() else {
+        return Ok(Value::Number(vm.args().len() as f64));
+    };
+
+    match index {
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => {
+            Ok(vm.args().get(*n as usize).map_or(Value::Nil, |arg| Value::String(LoxString::new(arg))))
+        },
+        _ => Err(LoxError::RuntimeError("Argument index must be a non-negative integer".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::LoxError, stdlib::Capabilities, value::Value, vm::VM};
+
+    fn vm(args: &[&str]) -> VM {
+        let mut vm = VM::new();
+        vm.set_args(args.iter().map(|arg| arg.to_string()).collect());
+        vm
+    }
+
+    #[test]
+    fn test_args() {
+        let mut vm = vm(&["input.txt", "3"]);
+        vm.interpret("var count = args();\nvar first = args(0);\nvar last = args(1);\nvar past = args(2);").unwrap();
+
+        assert_eq!(vm.get_global("count"), Some(Value::Number(2.0)));
+        assert_eq!(vm.get_global("first").unwrap().to_string(), "input.txt");
+        assert_eq!(vm.get_global("last").unwrap().to_string(), "3");
+        assert_eq!(vm.get_global("past"), Some(Value::Nil));
+    }
+
+    #[test]
+    fn test_invalid_index() {
+        for index in ["-1", "0.5", "\"0\""] {
+            let result = vm(&["a"]).interpret(&format!("args({});", index));
+            assert!(matches!(result, Err(LoxError::RuntimeError(msg)) if msg == "Argument index must be a non-negative integer"));
+        }
+    }
+
+    #[test]
+    fn test_sandboxed_vms_have_no_args() {
+        assert!(matches!(VM::with_capabilities(Capabilities::sandboxed()).interpret("args();"), Err(LoxError::RuntimeError(_))));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{error::LoxError, memory, objects::{LoxString, NativeFunction}, value::Value, vm::VM};
+use super::{count_or_part, integer, string};
+
+// Positions and lengths count characters (Unicode scalar values), not bytes.
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::new("len", 1, len),
+    NativeFunction::new("char_at", 2, char_at),
+    NativeFunction::variadic("substring", 2, 3, substring),
+    NativeFunction::new("index_of", 2, index_of),
+    NativeFunction::new("contains", 2, contains),
+    NativeFunction::new("starts_with", 2, starts_with),
+    NativeFunction::new("ends_with", 2, ends_with),
+    NativeFunction::new("upper", 1, upper),
+    NativeFunction::new("lower", 1, lower),
+    NativeFunction::new("trim", 1, trim),
+    NativeFunction::new("replace", 3, replace),
+    NativeFunction::new("repeat", 2, repeat),
+    NativeFunction::variadic("split", 2, 3, split),
+    NativeFunction::variadic("join", 1, usize::MAX, join),
+];
+
+fn new_string(s: &str) -> Value {
+    Value::String(LoxString::new(s))
+}
+
+fn out_of_range() -> LoxError {
+    LoxError::RuntimeError("String index out of range".to_string())
+}
+
+/// The byte offset of the character at `index`, which may be one past the
+/// last character.
+fn byte_offset(s: &str, index: usize) -> Result<usize, LoxError> {
+    s.char_indices().map(|(offset, _)| offset).chain(std::iter::once(s.len()))
+        .nth(index)
+        .ok_or_else(out_of_range)
+}
+
+fn len(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Number(string(args, 0)?.chars().count() as f64))
+}
+
+/// `char_at(s, index)` is the character at `index` as a string.
+fn char_at(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let c = string(args, 0)?.chars().nth(integer(args, 1)?).ok_or_else(out_of_range)?;
+    Ok(new_string(c.encode_utf8(&mut [0; 4])))
+}
+
+/// `substring(s, start[, end])` runs from `start` up to but excluding
+/// `end`, or to the end of `s`.
+fn substring(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let s = string(args, 0)?;
+    let start = byte_offset(s, integer(args, 1)?)?;
+    let end = if args.len() == 3 { byte_offset(s, integer(args, 2)?)? } else { s.len() };
+
+    if start > end {
+        return Err(out_of_range());
+    }
+    Ok(new_string(&s[start..end]))
+}
+
+/// `index_of(s, needle)` is the position of the first `needle` in `s`, or
+/// -1 if there is none.
+fn index_of(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let s = string(args, 0)?;
+
+    let index = match s.find(string(args, 1)?) {
+        Some(offset) => s[..offset].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::Number(index))
+}
+
+fn contains(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(string(args, 0)?.contains(string(args, 1)?)))
+}
+
+fn starts_with(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(string(args, 0)?.starts_with(string(args, 1)?)))
+}
+
+fn ends_with(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(Value::Bool(string(args, 0)?.ends_with(string(args, 1)?)))
+}
+
+fn upper(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(new_string(&string(args, 0)?.to_uppercase()))
+}
+
+fn lower(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(new_string(&string(args, 0)?.to_lowercase()))
+}
+
+/// `trim(s)` strips leading and trailing whitespace.
+fn trim(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(new_string(string(args, 0)?.trim()))
+}
+
+/// `replace(s, from, to)` replaces every `from` in `s` with `to`.
+fn replace(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    Ok(new_string(&string(args, 0)?.replace(string(args, 1)?, string(args, 2)?)))
+}
+
+/// `repeat(s, count)` is `s` repeated `count` times.
+fn repeat(vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let s = string(args, 0)?;
+    let count = integer(args, 1)?;
+
+    let Some(length) = s.len().checked_mul(count).filter(|length| *length <= isize::MAX as usize) else {
+        return Err(LoxError::RuntimeError("Repeated string is too long".to_string()));
+    };
+    vm.reserve(memory::string_size(length))?;
+
+    Ok(new_string(&s.repeat(count)))
+}
+
+/// Lox has no lists, so `split(s, separator)` returns how many parts there
+/// are and `split(s, separator, index)` the part at `index`, or nil past
+/// the last one. An empty separator splits `s` into characters.
+fn split(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let s = string(args, 0)?;
+    let separator = string(args, 1)?;
+
+    if separator.is_empty() {
+        count_or_part(args, 2, s.char_indices().map(|(offset, c)| &s[offset..offset + c.len_utf8()]))
+    } else {
+        count_or_part(args, 2, s.split(separator))
+    }
+}
+
+/// `join(separator, part...)` concatenates the parts with `separator`
+/// between them.
+fn join(_vm: &mut VM, args: &[Value]) -> Result<Value, LoxError> {
+    let separator = string(args, 0)?;
+
+    let parts = (1..args.len()).map(|index| string(args, index)).collect::<Result<Vec<&str>, LoxError>>()?;
+    Ok(new_string(&parts.join(separator)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::LoxError, value::Value, vm::VM};
+
+    fn eval(expression: &str) -> Result<Value, LoxError> {
+        let mut vm = VM::sandboxed();
+        vm.interpret(&format!("var result = {};", expression))?;
+        Ok(vm.get_global("result").unwrap())
+    }
+
+    fn text(expression: &str) -> String {
+        match eval(expression) {
+            Ok(Value::String(s)) => s.value.clone(),
+            result => panic!("expected a string from {}, got {:?}", expression, result),
+        }
+    }
+
+    fn error(expression: &str) -> String {
+        match eval(expression) {
+            Err(LoxError::RuntimeError(msg)) => msg,
+            result => panic!("expected a runtime error from {}, got {:?}", expression, result),
+        }
+    }
+
+    #[test]
+    fn test_positions_count_characters() {
+        assert_eq!(eval("len(\"naïve 🦀\")").unwrap(), Value::Number(7.0));
+        assert_eq!(text("char_at(\"naïve\", 2)"), "ï");
+        assert_eq!(text("substring(\"naïve 🦀\", 2, 5)"), "ïve");
+        assert_eq!(text("substring(\"naïve 🦀\", 6)"), "🦀");
+        assert_eq!(text("substring(\"abc\", 3)"), "");
+        assert_eq!(eval("index_of(\"naïve 🦀\", \"🦀\")").unwrap(), Value::Number(6.0));
+        assert_eq!(eval("index_of(\"abc\", \"x\")").unwrap(), Value::Number(-1.0));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(error("char_at(\"abc\", 3)"), "String index out of range");
+        assert_eq!(error("substring(\"abc\", 1, 4)"), "String index out of range");
+        assert_eq!(error("substring(\"abc\", 2, 1)"), "String index out of range");
+        assert_eq!(error("char_at(\"abc\", -1)"), "Argument 2 must be a non-negative integer but got -1");
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert_eq!(eval("contains(\"haystack\", \"st\")").unwrap(), Value::Bool(true));
+        assert_eq!(eval("starts_with(\"haystack\", \"hay\")").unwrap(), Value::Bool(true));
+        assert_eq!(eval("ends_with(\"haystack\", \"hay\")").unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_transformations() {
+        assert_eq!(text("upper(\"straße\")"), "STRASSE");
+        assert_eq!(text("lower(\"ÀB\")"), "àb");
+        assert_eq!(text("trim(\"\t padded \n\")"), "padded");
+        assert_eq!(text("replace(\"a-b-c\", \"-\", \"+\")"), "a+b+c");
+        assert_eq!(text("repeat(\"ab\", 3)"), "ababab");
+        assert_eq!(text("repeat(\"ab\", 0)"), "");
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        assert_eq!(eval("split(\"a,b,,c\", \",\")").unwrap(), Value::Number(4.0));
+        assert_eq!(text("split(\"a,b,,c\", \",\", 1)"), "b");
+        assert_eq!(text("split(\"a,b,,c\", \",\", 2)"), "");
+        assert_eq!(eval("split(\"a,b\", \",\", 2)").unwrap(), Value::Nil);
+        assert_eq!(text("split(\"hé\", \"\", 1)"), "é");
+        assert_eq!(text("join(\", \", \"a\", \"b\", \"c\")"), "a, b, c");
+        assert_eq!(text("join(\"-\")"), "");
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        assert_eq!(error("len(42)"), "Argument 1 must be a string but got number");
+        assert_eq!(error("join(\", \", \"a\", nil)"), "Argument 3 must be a string but got nil");
+        assert_eq!(error("join()"), "Expected at least 1 arguments but got 0");
+        assert_eq!(error("trim()"), "Expected 1 arguments but got 0");
+    }
+
+    #[test]
+    fn test_repeat_respects_memory_limit() {
+        let mut vm = VM::sandboxed();
+        vm.set_memory_limit(Some(1024 * 1024));
+
+        assert!(matches!(vm.interpret("repeat(\"abc\", 1000000);"), Err(LoxError::OutOfMemory(_))));
+        assert!(matches!(vm.interpret("repeat(\"abc\", pow(10, 30));"), Err(LoxError::RuntimeError(msg)) if msg == "Repeated string is too long"));
+    }
+}
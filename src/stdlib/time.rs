@@ -0,0 +1,10 @@
+use crate::{error::LoxError, objects::NativeFunction, value::Value, vm::VM};
+
+pub const NATIVES: &[NativeFunction] = &[
+    NativeFunction::new("clock", 0, clock),
+];
+
+fn clock(vm: &mut VM, _args: &[Value]) -> Result<Value, LoxError> {
+    let elapsed = vm.cpu_time().as_secs_f64();
+    Ok(Value::Number(elapsed))
+}
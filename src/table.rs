@@ -1,11 +1,12 @@
 use std::alloc::{alloc, dealloc, Layout};
 use crate::value::Value;
 use crate::objects::LoxString;
-use std::ptr::{null_mut, read, write};
-use std::rc::Rc;
+use std::marker::PhantomData;
+use std::ptr::{drop_in_place, null_mut, read, write};
+use std::sync::Arc;
 
 pub struct Entry {
-    key: Option<Rc<LoxString>>,
+    key: Option<Arc<LoxString>>,
     value: Value,
 }
 
@@ -16,6 +17,16 @@ pub struct Table {
     entries: *mut Entry,
 }
 
+// SAFETY: the entries are owned by the table alone and only hold `Send`
+// keys and values, so the allocation can move to another thread with it.
+unsafe impl Send for Table {}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table::new()
+    }
+}
+
 impl Table {
     const MAX_LOAD: f32 = 0.75;
 
@@ -27,7 +38,7 @@ impl Table {
         }
     }
 
-    pub fn get(&self, key: Rc<LoxString>) -> Option<Value> {
+    pub fn get(&self, key: Arc<LoxString>) -> Option<Value> {
         if self.count == 0 {
             return None;
         }
@@ -42,7 +53,7 @@ impl Table {
         }
     }
 
-    pub fn set(&mut self, key: Rc<LoxString>, value: Value) -> bool {
+    pub fn set(&mut self, key: Arc<LoxString>, value: Value) -> bool {
         unsafe {
             if self.count + 1 > (self.capacity as f32 * Self::MAX_LOAD) as usize {
                 let new_capacity = if self.capacity == 0 { 8 } else { self.capacity * 2 };
@@ -63,7 +74,7 @@ impl Table {
         }
     }
 
-    pub fn delete(&mut self, key: Rc<LoxString>) -> bool {
+    pub fn delete(&mut self, key: Arc<LoxString>) -> bool {
         if self.count == 0 {
             return false;
         }
@@ -121,71 +132,76 @@ impl Table {
         }
     }
 
-    unsafe fn find_entry(entries: *mut Entry, key: Rc<LoxString>, capacity: usize) -> *mut Entry {
-        debug_assert!(capacity.is_power_of_two() && capacity > 0);
+    unsafe fn find_entry(entries: *mut Entry, key: Arc<LoxString>, capacity: usize) -> *mut Entry {
+        unsafe {
+            debug_assert!(capacity.is_power_of_two() && capacity > 0);
 
-        let mut index = key.hash & (capacity - 1);
+            let mut index = key.hash & (capacity - 1);
 
-        loop {
-            let entry = entries.add(index);
+            loop {
+                let entry = entries.add(index);
 
-            match (*entry).key {
-                Some(ref k) => {
-                    if **k == *key {
-                        return entry;
-                    }
-                },
-                None => {
-                    match (*entry).value {
-                        Value::Nil | Value::Bool(true) => return entry,
-                        _ => continue
+                match (*entry).key {
+                    Some(ref k) => {
+                        if **k == *key {
+                            return entry;
+                        }
+                    },
+                    None => {
+                        match (*entry).value {
+                            Value::Nil | Value::Bool(true) => return entry,
+                            _ => continue
+                        }
                     }
                 }
-            }
 
-            index = (index + 1) & (capacity - 1);
+                index = (index + 1) & (capacity - 1);
+            }
         }
     }
 
     unsafe fn adjust_capacity(&mut self, new_capacity: usize) {
-        let entries = alloc(Layout::array::<Entry>(new_capacity).unwrap()) as *mut Entry;
+        unsafe {
+            let entries = alloc(Layout::array::<Entry>(new_capacity).unwrap()) as *mut Entry;
 
-        for i in 0..new_capacity {
-            let entry = entries.add(i);
-            write(entry, Entry { key: None, value: Value::Nil });
-        }
+            for i in 0..new_capacity {
+                let entry = entries.add(i);
+                write(entry, Entry { key: None, value: Value::Nil });
+            }
+
+            self.count = 0;
+            for i in 0..self.capacity {
+                let entry = self.entries.add(i);
 
-        self.count = 0;
-        for i in 0..self.capacity {
-            let entry = self.entries.add(i);
+                match (*entry).key {
+                    Some(ref k) => {
+                        let dest = Self::find_entry(entries, k.clone(), new_capacity);
+                        (*dest).key = (*entry).key.take();
+                        (*dest).value = read(&(*entry).value);
 
-            match (*entry).key {
-                Some(ref k) => {
-                    let dest = Self::find_entry(entries, k.clone(), new_capacity);
-                    (*dest).key = (*entry).key.take();
-                    (*dest).value = read(&(*entry).value);
+                        self.count += 1;
+                    },
+                    None => continue
+                }
+            }
 
-                    self.count += 1;
-                },
-                None => continue
+            if self.capacity > 0 {
+                dealloc (
+                    self.entries.cast(),
+                    Layout::array::<Entry>(self.capacity).unwrap()
+                );
             }
-        }
 
-        if self.capacity > 0 {
-            dealloc (
-                self.entries.cast(),
-                Layout::array::<Entry>(self.capacity).unwrap()
-            );
+            self.entries = entries;
+            self.capacity = new_capacity;
         }
-
-        self.entries = entries;
-        self.capacity = new_capacity;
     }
 
-    pub fn iter(&self) -> IterTable {
+    pub fn iter(&self) -> IterTable<'_> {
         IterTable {
             current: self.entries,
-            end: unsafe { self.entries.add(self.capacity) }
+            end: unsafe { self.entries.add(self.capacity) },
+            table: PhantomData,
         }
     }
 }
@@ -194,6 +210,10 @@ impl Drop for Table {
     fn drop(&mut self) {
         unsafe {
             if !self.entries.is_null() {  
+                for i in 0..self.capacity {
+                    drop_in_place(self.entries.add(i));
+                }
+
                 dealloc(
                     self.entries.cast(),
                     Layout::array::<Entry>(self.capacity).unwrap()
@@ -203,23 +223,23 @@ impl Drop for Table {
     }
 }
 
-pub struct IterTable {
+pub struct IterTable<'a> {
     current: *mut Entry,
     end: *const Entry,
+    table: PhantomData<&'a Table>,
 }
 
-impl Iterator for IterTable {
-    type Item = Entry;
+impl Iterator for IterTable<'_> {
+    type Item = (Arc<LoxString>, Value);
 
     fn next(&mut self) -> Option<Self::Item> {
         while !std::ptr::eq(self.current, self.end) {
             unsafe {
                 let entry = self.current;
                 self.current = self.current.add(1);
-                if (*entry).key.is_none() {
-                    continue;
+                if let Some(ref k) = (*entry).key {
+                    return Some((k.clone(), (*entry).value.clone()));
                 }
-                return Some(read(entry));
             }
         }
 
@@ -354,4 +374,24 @@ mod tests {
         assert_eq!(table.get(LoxString::new("e")), Some(Value::Bool(true)));
         assert_eq!(table.get(LoxString::new("f")), Some(Value::Bool(true)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_iter() {
+        let mut table = Table::new();
+
+        table.set(LoxString::new("a"), Value::Number(1.0));
+        table.set(LoxString::new("b"), Value::Number(2.0));
+        table.set(LoxString::new("c"), Value::Number(3.0));
+        table.delete(LoxString::new("b"));
+
+        let mut entries: Vec<(String, Value)> = table.iter()
+            .map(|(k, v)| (k.value.clone(), v))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(entries, vec![
+            ("a".to_string(), Value::Number(1.0)),
+            ("c".to_string(), Value::Number(3.0)),
+        ]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use crate::{objects::Function, value::Value, vm::VM};
+
+/// A `Write` whose clones share what is written to them, for reading back
+/// what a `VM` printed.
+#[derive(Clone, Default)]
+pub struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// The error that failed the test, `None` if it passed.
+    pub error: Option<String>,
+    /// What the script and the test printed.
+    pub output: String,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The results of every test declared in one script.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestReport {
+    pub path: String,
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    /// One line per test, then the output and error of each failure.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "running {} tests in {}", self.results.len(), self.path);
+
+        for result in &self.results {
+            let status = if result.passed() { "ok" } else { "FAILED" };
+            let _ = writeln!(report, "test {} ... {}", result.name, status);
+        }
+
+        for result in self.results.iter().filter(|r| !r.passed()) {
+            let _ = write!(report, "\n---- {} ----\n{}", result.name, result.output);
+            let _ = writeln!(report, "{}", result.error.as_deref().unwrap_or_default());
+        }
+
+        report
+    }
+}
+
+/// Runs each test declared in `script`, every one in a fresh VM that first
+/// runs the script's top level code. `vm` sets up each VM, e.g. to choose
+/// its capabilities.
+pub fn run_tests(path: &str, script: &Arc<Function>, vm: impl Fn() -> VM) -> TestReport {
+    let results = script.tests.iter().map(|test| {
+        let output = Capture::default();
+        let mut vm = vm();
+        vm.set_output(Box::new(output.clone()));
+
+        let error = vm.execute(script.clone())
+            .and_then(|_| vm.call_function(Value::Function(test.clone()), &[]))
+            .err()
+            .map(|e| e.to_string());
+
+        TestResult { name: test.name.value.clone(), error, output: output.text() }
+    }).collect();
+
+    TestReport { path: path.to_string(), results }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{analyze, compile};
+
+    const SCRIPT: &str = "\
+var counter = 0;
+fun add(a, b) { return a + b; }
+
+test \"adds\" {
+    assert_eq(add(1, 2), 3);
+}
+
+test \"fails\" {
+    print \"before\";
+    assert_eq(add(1, 2), 4, \"sum\");
+}
+
+test \"isolated\" {
+    counter = counter + 1;
+    assert_eq(counter, 1);
+}
+
+test \"isolated again\" {
+    counter = counter + 1;
+    assert_eq(counter, 1);
+}
+";
+
+    #[test]
+    fn test_declarations_are_collected() {
+        let script = compile(SCRIPT).unwrap();
+        let names: Vec<&str> = script.tests.iter().map(|t| t.name.value.as_str()).collect();
+
+        assert_eq!(names, vec!["adds", "fails", "isolated", "isolated again"]);
+        assert!(script.tests.iter().all(|t| t.arity == 0));
+    }
+
+    #[test]
+    fn test_tests_do_not_run_with_the_script() {
+        let mut vm = VM::new();
+        vm.interpret("test \"never\" { assert(false); }\nvar test = 1;\nprint test;").unwrap();
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let report = run_tests("math.lox", &compile(SCRIPT).unwrap(), VM::new);
+
+        assert_eq!(report.passed(), 3);
+        assert_eq!(report.failed(), 1);
+
+        let failure = &report.results[1];
+        assert_eq!(failure.error.as_deref(), Some("Assertion failed: sum: expected 4 but got 3"));
+        assert_eq!(failure.output, "before\n");
+    }
+
+    #[test]
+    fn test_report() {
+        let report = run_tests("math.lox", &compile(SCRIPT).unwrap(), VM::new).report();
+
+        assert!(report.starts_with("running 4 tests in math.lox\ntest adds ... ok\ntest fails ... FAILED\n"));
+        assert!(report.ends_with("\n---- fails ----\nbefore\nAssertion failed: sum: expected 4 but got 3\n"));
+    }
+
+    #[test]
+    fn test_invalid_declarations() {
+        let messages = |source| analyze(source).diagnostics.into_iter().map(|d| d.message).collect::<Vec<_>>();
+
+        assert_eq!(messages("fun f() { test \"inner\" {} }"), vec!["Tests must be declared at top level."]);
+        assert_eq!(messages("test \"a\" {}\ntest \"a\" {}"), vec!["Already a test with this name."]);
+    }
+}
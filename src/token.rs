@@ -28,6 +28,8 @@ pub struct Token<'a> {
     pub token_type: TokenType,
     pub lexeme: &'a str,
     pub line: usize,
+    /// Byte offset of the start of the lexeme in the source.
+    pub offset: usize,
 }
 
 impl<'a> Token<'a> {
@@ -36,6 +38,7 @@ impl<'a> Token<'a> {
             token_type: TokenType::Eof,
             lexeme: s,
             line: 0,
+            offset: 0,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use serde_json::{json, Value as Json};
+use crate::{debugger::describe, disassembler::opcode_name, instrument::{self, Instrument}, objects::Function, op::OpCode, value::Value};
+
+fn operand(instruction: OpCode) -> Option<u16> {
+    match instruction {
+        OpCode::Constant(n) | OpCode::GetLocal(n) | OpCode::SetLocal(n) | OpCode::GetGlobal(n)
+        | OpCode::DefGlobal(n) | OpCode::SetGlobal(n) | OpCode::Call(n) => Some(n as u16),
+        OpCode::Jump(n) | OpCode::JumpIfFalse(n) | OpCode::Loop(n) => Some(n),
+        _ => None,
+    }
+}
+
+struct State {
+    output: Box<dyn Write + Send>,
+    functions: Option<Vec<String>>,
+    step: u64,
+    error: Option<io::Error>,
+}
+
+impl State {
+    fn traces(&self, name: &str) -> bool {
+        self.functions.as_ref().is_none_or(|functions| functions.iter().any(|f| f == name))
+    }
+
+    fn write(&mut self, record: Json) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.output, "{}", record) {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Writes every call, return and instruction as one JSON object per line.
+///
+/// Records hold no timings, so traces of the same program from two builds
+/// of the interpreter can be diffed. `step` counts every instruction run,
+/// traced or not. Clones share the same output.
+#[derive(Clone)]
+pub struct Tracer(Arc<Mutex<State>>);
+
+impl Tracer {
+    pub fn new(output: impl Write + Send + 'static) -> Tracer {
+        Tracer::with_filter(output, None)
+    }
+
+    /// Only traces the functions named in `functions`, `<script>` being the
+    /// top level code.
+    pub fn filtered(output: impl Write + Send + 'static, functions: &[String]) -> Tracer {
+        Tracer::with_filter(output, Some(functions.to_vec()))
+    }
+
+    fn with_filter(output: impl Write + Send + 'static, functions: Option<Vec<String>>) -> Tracer {
+        Tracer(Arc::new(Mutex::new(State { output: Box::new(output), functions, step: 0, error: None })))
+    }
+
+    /// Flushes the output, reporting the first error met while tracing.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => state.output.flush(),
+        }
+    }
+
+    fn frame_event(&self, event: &str, function: &Function, depth: usize) {
+        let mut state = self.0.lock().unwrap();
+        let name = instrument::function_name(function);
+        if !state.traces(&name) {
+            return;
+        }
+
+        let record = json!({ "event": event, "step": state.step, "depth": depth, "function": name });
+        state.write(record);
+    }
+}
+
+impl Instrument for Tracer {
+    fn on_call(&mut self, function: &Function, depth: usize) {
+        self.frame_event("call", function, depth);
+    }
+
+    fn on_return(&mut self, function: &Function, depth: usize) {
+        self.frame_event("return", function, depth);
+    }
+
+    fn on_instruction(&mut self, function: &Function, offset: usize, line: u16, depth: usize, stack: &[Value]) {
+        let mut state = self.0.lock().unwrap();
+        state.step += 1;
+
+        let name = instrument::function_name(function);
+        if !state.traces(&name) {
+            return;
+        }
+
+        let instruction = function.block.code[offset];
+        let record = json!({
+            "event": "instruction",
+            "step": state.step,
+            "depth": depth,
+            "function": name,
+            "line": line,
+            "offset": offset,
+            "opcode": opcode_name(instruction),
+            "operand": operand(instruction),
+            "stack": stack.iter().map(describe).collect::<Vec<String>>(),
+        });
+        state.write(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Capture, vm::VM};
+
+    fn trace(source: &str, functions: Option<&[&str]>) -> Vec<Json> {
+        let buffer = Capture::default();
+        let tracer = match functions {
+            Some(functions) => {
+                let functions: Vec<String> = functions.iter().map(|f| f.to_string()).collect();
+                Tracer::filtered(buffer.clone(), &functions)
+            },
+            None => Tracer::new(buffer.clone()),
+        };
+
+        let mut vm = VM::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.add_instrument(Box::new(tracer.clone()));
+        vm.interpret(source).unwrap();
+        tracer.finish().unwrap();
+
+        let text = buffer.text();
+        text.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    const PROGRAM: &str = "fun add(a, b) {\n    return a + b;\n}\nprint add(1, 2);\n";
+
+    #[test]
+    fn test_records() {
+        let records = trace("var x = 1;", None);
+
+        assert_eq!(records[0], json!({ "event": "call", "step": 0, "depth": 1, "function": "<script>" }));
+        assert_eq!(records[1], json!({
+            "event": "instruction", "step": 1, "depth": 1, "function": "<script>", "line": 1,
+            "offset": 0, "opcode": "Constant", "operand": 1, "stack": ["<fn script>"],
+        }));
+        assert_eq!(records.last().unwrap()["event"], "return");
+    }
+
+    #[test]
+    fn test_stack_snapshot() {
+        let records = trace(PROGRAM, None);
+        let add = records.iter().find(|r| r["function"] == "add" && r["opcode"] == "Add").unwrap();
+
+        assert_eq!(add["depth"], 2);
+        assert_eq!(add["line"], 2);
+        assert_eq!(add["stack"], json!(["<fn add>", "1", "2", "1", "2"]));
+    }
+
+    #[test]
+    fn test_function_filter() {
+        let records = trace(PROGRAM, Some(&["add"]));
+
+        assert!(records.iter().all(|r| r["function"] == "add"));
+        assert_eq!(records.first().unwrap()["event"], "call");
+        assert_eq!(records.last().unwrap()["event"], "return");
+        assert_eq!(records.iter().filter(|r| r["event"] == "instruction").count(), 4);
+
+        // Steps still count the instructions of filtered out functions.
+        assert!(records[1]["step"].as_u64().unwrap() > 1);
+    }
+
+    #[test]
+    fn test_traces_are_deterministic() {
+        assert_eq!(trace(PROGRAM, None), trace(PROGRAM, None));
+    }
+}
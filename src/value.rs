@@ -1,5 +1,5 @@
 use std::{fmt};
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fmt::Display;
 use crate::objects::{LoxString, Function, NativeFunction};
 
@@ -7,8 +7,8 @@ use crate::objects::{LoxString, Function, NativeFunction};
 pub enum Value {
     Number(f64),
     Bool(bool),
-    String(Rc<LoxString>),
-    Function(Rc<Function>),
+    String(Arc<LoxString>),
+    Function(Arc<Function>),
     NativeFunction(NativeFunction),
     Nil,
 }
@@ -52,6 +52,17 @@ impl Value {
         !self.is_truthy()
     }
 
+    /// The name of the value's type, as shown in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::NativeFunction(_) => "function",
+            Value::Nil => "nil",
+        }
+    }
+
     pub fn as_number(&self) -> Option<f64> {
         if let Value::Number(n) = self {
             Some(*n)
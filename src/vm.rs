@@ -1,23 +1,35 @@
 use cpu_time::ProcessTime;
 use std::{ptr::null_mut};
-use std::rc::Rc;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use crate::{
-    block::Block, compiler::compile, error::LoxError, op::OpCode, value::Value, objects::{LoxString, Function, NativeFunction}, table::Table
+    disassembler, compiler::compile, error::LoxError, op::OpCode, value::Value, objects::{LoxString, Function, NativeFunction, ObjectType}, table::Table,
+    memory::{self, Heap, MemoryStats, Tracer}, stdlib::Capabilities, snapshot::{Snapshot, SnapshotError},
+    debugger::{DebugHook, DebugState, FrameInfo, PauseReason, Resume}, instrument::Instrument
 };
 
 #[derive(Clone, Debug)]
 struct CallFrame {
-    function: Option<Rc<Function>>,
+    function: Option<Arc<Function>>,
     ip: *const OpCode,
     slots: usize,
+    line: u16,
 }
 
+// SAFETY: `ip` points into the code of the function the frame holds an
+// `Arc` to. That code is never mutated or moved after compilation, so the
+// pointer stays valid on whichever thread owns the frame.
+unsafe impl Send for CallFrame {}
+
 impl CallFrame {
-    fn new(function: Rc<Function>, slot: usize) -> CallFrame {
+    fn new(function: Arc<Function>, slot: usize) -> CallFrame {
         let mut cf = CallFrame {
             function: Some(function),
             ip: null_mut(),
             slots: slot,
+            line: 0,
         };
 
         cf.ip = cf.function.as_ref().unwrap().block.code.as_ptr();
@@ -30,33 +42,98 @@ impl CallFrame {
             function: None,
             ip: null_mut(),
             slots: 0,
+            line: 0,
         }
     }
+
+    /// Offset of the instruction being executed, or of the call in progress.
+    fn offset(&self) -> usize {
+        let function = self.function.as_ref().unwrap();
+        let offset = unsafe { self.ip.offset_from(function.block.code.as_ptr()) as usize };
+        offset.saturating_sub(1)
+    }
+}
+
+/// Lets another thread stop a running `VM` at its next limit check. An
+/// interrupt sent between runs stops the next one.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
 pub struct VM {
     frames: [CallFrame; VM::MAX_FRAMES],
     frame_count: usize,
-    stack: [Value; VM::MAX_STACK],
+    stack: Box<[Value]>,
     stack_top: usize,
-    strings: Table,
     globals: Table,
     init_time: ProcessTime,
+    instruction_count: u64,
+    instruction_budget: Option<u64>,
+    time_limit: Option<Duration>,
+    deadline: Option<Instant>,
+    interrupted: Arc<AtomicBool>,
+    heap: Heap,
+    capabilities: Capabilities,
+    args: Vec<String>,
+    // The call frames where the last run's uncaught error was raised.
+    error_frames: Vec<FrameInfo>,
+    debug: DebugState,
+    output: Box<dyn Write + Send>,
+    // `None` reads from stdin, locking it only while reading.
+    input: Option<Box<dyn BufRead + Send>>,
+    instruments: Vec<Box<dyn Instrument>>,
+    trace: bool,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
 }
 
 impl VM {
     const MAX_FRAMES: usize = 64;
     const MAX_STACK: usize = Self::MAX_FRAMES * u8::MAX as usize;
+    // The clock and the interrupt flag are only polled every 1024 instructions.
+    const LIMIT_CHECK_MASK: u64 = 1024 - 1;
 
+    /// Creates a VM with every standard library module available.
     pub fn new() -> VM {
+        VM::with_capabilities(Capabilities::all())
+    }
+
+    /// Creates a VM that only exposes pure natives to its scripts.
+    pub fn sandboxed() -> VM {
+        VM::with_capabilities(Capabilities::sandboxed())
+    }
+
+    pub fn with_capabilities(capabilities: Capabilities) -> VM {
         let mut vm =VM {
             frames: std::array::from_fn(|_| CallFrame::dangling()),
             frame_count: 0,
-            stack: std::array::from_fn(|_| Value::Nil),
+            stack: vec![Value::Nil; VM::MAX_STACK].into_boxed_slice(),
             stack_top: 0,
-            strings: Table::new(),
             globals: Table::new(),
             init_time: ProcessTime::now(),
+            instruction_count: 0,
+            instruction_budget: None,
+            time_limit: None,
+            deadline: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            heap: Heap::new(),
+            capabilities,
+            args: Vec::new(),
+            error_frames: Vec::new(),
+            debug: DebugState::new(),
+            output: Box::new(std::io::stdout()),
+            input: None,
+            instruments: Vec::new(),
+            trace: cfg!(feature = "debug_trace"),
         };
 
         vm.init_vm();
@@ -65,7 +142,152 @@ impl VM {
     }
 
     fn init_vm(&mut self) {
-        self.define_native("clock", NativeFunction(clock));
+        for module in self.capabilities.modules() {
+            for native in module.natives() {
+                self.define_native(*native);
+            }
+            for (name, value) in module.constants() {
+                self.globals.set(LoxString::from_string(name), Value::Number(*value));
+            }
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// The command line arguments passed to the script, read by `args()`.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(LoxString::new(name))
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(&self.globals, |name, native| {
+            self.builtin_native(name).as_ref() == Some(native)
+        })
+    }
+
+    /// Loads the globals of `snapshot` into this VM, replacing any global
+    /// with the same name.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let globals = snapshot.globals(|name| self.builtin_native(name))?;
+
+        for (name, value) in globals {
+            self.globals.set(name, value);
+        }
+
+        Ok(())
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<VM, SnapshotError> {
+        let mut vm = VM::new();
+        vm.restore(snapshot)?;
+        Ok(vm)
+    }
+
+    fn builtin_native(&self, name: &str) -> Option<NativeFunction> {
+        self.capabilities.modules()
+            .flat_map(|module| module.natives())
+            .find(|native| native.name == name)
+            .copied()
+    }
+
+    /// Processor time used since the VM was created.
+    pub fn cpu_time(&self) -> Duration {
+        self.init_time.elapsed()
+    }
+
+    /// Limits each call to `interpret` to `budget` executed instructions.
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.instruction_budget = budget;
+    }
+
+    /// Limits each call to `interpret` to `limit` of wall-clock time.
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupted.clone())
+    }
+
+    /// Number of instructions executed by the last call to `interpret`.
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// Caps the estimated size of the objects reachable from the VM.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.heap.set_limit(limit);
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.heap.stats(self.live_bytes())
+    }
+
+    fn live_bytes(&self) -> usize {
+        let mut tracer = Tracer::new();
+
+        for value in self.stack[..self.stack_top].iter() {
+            tracer.mark_value(value);
+        }
+
+        for frame in self.frames[..self.frame_count].iter() {
+            tracer.mark_function(frame.function.as_ref().unwrap());
+        }
+
+        for (name, value) in self.globals.iter() {
+            tracer.mark_string(&name);
+            tracer.mark_value(&value);
+        }
+
+        tracer.bytes()
+    }
+
+    /// Fails if `size` more bytes would not fit under the memory limit, so
+    /// natives can check before building a large value.
+    pub(crate) fn reserve(&mut self, size: usize) -> Result<(), LoxError> {
+        if !self.heap.fits(size) {
+            self.heap.sync(self.live_bytes());
+
+            if !self.heap.fits(size) {
+                return Err(LoxError::OutOfMemory(self.heap.limit().unwrap()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn allocate(&mut self, object_type: ObjectType, size: usize) -> Result<(), LoxError> {
+        self.reserve(size)?;
+        self.heap.track(object_type, size);
+        Ok(())
+    }
+
+    fn allocate_value(&mut self, value: &Value) -> Result<(), LoxError> {
+        match value {
+            Value::String(s) => self.allocate(ObjectType::LoxString, memory::string_size(s.value.len())),
+            Value::Function(f) => self.allocate_function(f),
+            _ => Ok(()),
+        }
+    }
+
+    fn allocate_function(&mut self, function: &Function) -> Result<(), LoxError> {
+        self.allocate(ObjectType::Function, memory::function_size(function))?;
+
+        for constant in function.block.constants.iter() {
+            self.allocate_value(constant)?;
+        }
+
+        Ok(())
     }
 
     fn push(&mut self, value: Value) {
@@ -102,31 +324,256 @@ impl VM {
 
     pub fn interpret(&mut self, source: &str) -> Result<(), LoxError> {
         let function = compile(source)?;
-        self.push(Value::Function(function.clone()));
-        self.call(function.clone(), 0)?;
-        self.run()
+        self.execute(function)
+    }
+
+    /// Runs an already compiled script.
+    pub fn execute(&mut self, function: Arc<Function>) -> Result<(), LoxError> {
+        self.instruction_count = 0;
+        self.deadline = self.time_limit.map(|limit| Instant::now() + limit);
+        self.error_frames.clear();
+        self.heap.sync(self.live_bytes());
+
+        let result = self.allocate_function(&function).and_then(|_| {
+            self.push(Value::Function(function.clone()));
+            self.call(function.clone(), 0)
+        }).and_then(|_| self.run(0));
+
+        if result.is_err() {
+            self.error_frames = self.frames();
+            self.reset_stack();
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Calls a function value from the host, returning its result.
+    ///
+    /// Works both between and during runs, e.g. from a native or a debug
+    /// hook. On error the stack is unwound to where it was before the call.
+    pub fn call_function(&mut self, callee: Value, args: &[Value]) -> Result<Value, LoxError> {
+        let (stack_top, frame_count) = (self.stack_top, self.frame_count);
+
+        self.push(callee.clone());
+        for arg in args {
+            self.push(arg.clone());
+        }
+
+        let result = match callee {
+            Value::Function(f) => self.call(f, args.len()).and_then(|_| self.run(frame_count)),
+            _ => self.call_value(args.len()).map(|_| self.pop()),
+        };
+
+        if result.is_err() {
+            self.stack_top = stack_top;
+            self.frame_count = frame_count;
+        }
+
+        result
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.output = output;
+    }
+
+    /// Reads the input of `read_line`, `read_all` and `input` from `input`
+    /// instead of stdin.
+    pub fn set_input(&mut self, input: Box<dyn BufRead + Send>) {
+        self.input = Some(input);
+    }
+
+    pub(crate) fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub(crate) fn with_input<T>(&mut self, f: impl FnOnce(&mut dyn BufRead) -> T) -> T {
+        match &mut self.input {
+            Some(input) => f(input),
+            None => f(&mut std::io::stdin().lock()),
+        }
+    }
+
+    /// Registers an instrument that observes every call and instruction.
+    pub fn add_instrument(&mut self, instrument: Box<dyn Instrument>) {
+        self.instruments.push(instrument);
+    }
+
+    pub fn clear_instruments(&mut self) {
+        self.instruments.clear();
+    }
+
+    /// Prints the stack and each instruction as it runs. On by default
+    /// when built with the `debug_trace` feature.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Attaches a debugger that is consulted at every new line.
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>, stop_on_entry: bool) {
+        self.debug.attach(hook, stop_on_entry);
+    }
+
+    pub fn clear_debug_hook(&mut self) {
+        self.debug.hook = None;
+    }
+
+    pub fn add_breakpoint(&mut self, line: u16) {
+        self.debug.breakpoints.insert(line);
+    }
+
+    pub fn remove_breakpoint(&mut self, line: u16) -> bool {
+        self.debug.breakpoints.remove(&line)
     }
 
-    fn run(&mut self) -> Result<(), LoxError> {
+    pub fn breakpoints(&self) -> Vec<u16> {
+        let mut lines: Vec<u16> = self.debug.breakpoints.iter().copied().collect();
+        lines.sort();
+        lines
+    }
+
+    /// The call frames, innermost first, where the error returned by the
+    /// last `execute` was raised, for printing a stack trace. Errors caught
+    /// by the script, as with `assert_throws`, leave no trace.
+    pub fn error_frames(&self) -> &[FrameInfo] {
+        &self.error_frames
+    }
+
+    /// Active call frames, innermost first.
+    pub fn frames(&self) -> Vec<FrameInfo> {
+        (0..self.frame_count).rev().map(|index| {
+            let frame = &self.frames[index];
+            let function = frame.function.as_ref().unwrap();
+
+            FrameInfo {
+                index,
+                function: function.name.value.clone(),
+                line: function.block.lines[frame.offset()],
+            }
+        }).collect()
+    }
+
+    /// Named locals in scope in frame `index`, in declaration order.
+    pub fn locals(&self, index: usize) -> Vec<(String, Value)> {
+        if index >= self.frame_count {
+            return Vec::new();
+        }
+
+        let frame = &self.frames[index];
+        let mut locals: Vec<_> = Vec::new();
+
+        for local in frame.function.as_ref().unwrap().locals_at(frame.offset()) {
+            if !locals.iter().any(|(name, _)| *name == local.name) {
+                locals.push((local.name.clone(), local.slot));
+            }
+        }
+
+        locals.sort_by_key(|(_, slot)| *slot);
+        locals.into_iter()
+            .map(|(name, slot)| (name, self.stack[frame.slots + slot as usize].clone()))
+            .collect()
+    }
+
+    /// Global variables sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self.globals.iter()
+            .map(|(name, value)| (name.value.clone(), value))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Evaluates `expression` with the locals of frame `index` in scope.
+    pub fn evaluate(&mut self, index: usize, expression: &str) -> Result<Value, LoxError> {
+        let (names, values): (Vec<_>, Vec<_>) = self.locals(index).into_iter().unzip();
+        let source = format!("fun evaluate({}) {{ return {}; }}", names.join(", "), expression);
+
+        let script = compile(&source)?;
+        let function = script.block.constants.iter().find_map(|constant| match constant {
+            Value::Function(f) => Some(f.clone()),
+            _ => None,
+        }).ok_or_else(|| LoxError::CompileError("Invalid expression".to_string()))?;
+
+        self.call_function(Value::Function(function), &values)
+    }
+
+    fn pause(&mut self, reason: PauseReason) -> Result<(), LoxError> {
+        // The hook is detached while it runs so evaluating expressions
+        // from inside it cannot pause again.
+        let Some(mut hook) = self.debug.hook.take() else {
+            return Ok(());
+        };
+
+        let resume = hook.on_pause(self, reason);
+        self.debug.hook = Some(hook);
+        self.debug.resume(resume, self.frame_count);
+
+        match resume {
+            Resume::Stop => Err(LoxError::Interrupted),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<(), LoxError> {
+        if let Some(budget) = self.instruction_budget && self.instruction_count > budget {
+            return Err(LoxError::InstructionLimit(budget));
+        }
+
+        if self.instruction_count & VM::LIMIT_CHECK_MASK == 0 {
+            if self.interrupted.swap(false, Ordering::Relaxed) {
+                return Err(LoxError::Interrupted);
+            }
+
+            if let Some(deadline) = self.deadline && Instant::now() >= deadline {
+                return Err(LoxError::Timeout(self.time_limit.unwrap()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs until the frame above `base` returns, yielding its result.
+    fn run(&mut self, base: usize) -> Result<Value, LoxError> {
         let mut current_frame = unsafe { &mut *(&mut self.frames[self.frame_count - 1] as *mut CallFrame) };
         let mut current_block = &current_frame.function.as_ref().unwrap().block;
 
         loop {
             let op = unsafe { *current_frame.ip };
 
-            #[cfg(feature = "debug_trace")]
-            {
+            if self.trace {
                 let offset = unsafe { current_frame.ip.offset_from(current_block.code.as_ptr()) as usize };
                 print!("stack -> ");
                 for i in 0..self.stack_top {
                     print!("[{}] ", self.stack[i]);
                 }
                 println!();
-                self.disassemble_instruction(&current_frame, &current_block, offset);
+                self.disassemble_instruction(current_frame, offset);
             }
 
             current_frame.ip = unsafe { current_frame.ip.offset(1) };
 
+            self.instruction_count += 1;
+            self.check_limits()?;
+
+            if self.debug.hook.is_some() {
+                let line = current_block.lines[current_frame.offset()];
+                let new_line = line != current_frame.line;
+                current_frame.line = line;
+
+                if let Some(reason) = self.debug.should_pause(self.frame_count, line, new_line) {
+                    self.pause(reason)?;
+                }
+            }
+
+            if !self.instruments.is_empty() {
+                let function = current_frame.function.as_ref().unwrap();
+                let offset = current_frame.offset();
+                let stack = &self.stack[current_frame.slots..self.stack_top];
+                for instrument in self.instruments.iter_mut() {
+                    instrument.on_instruction(function, offset, current_block.lines[offset], self.frame_count, stack);
+                }
+            }
+
             match op {
                 OpCode::Constant(index) => {
                     let value = current_block.read_constant(index);
@@ -189,6 +636,7 @@ impl VM {
                     match (&a, &b) {
                         (Value::Number(a), Value::Number(b)) => self.push(Value::Number(a + b)),
                         (Value::String(a), Value::String(b)) => {
+                            self.allocate(ObjectType::LoxString, memory::string_size(a.value.len() + b.value.len()))?;
                             let result = format!("{}{}", a.value, b.value);
                             self.push(Value::String(LoxString::new(&result)))
                         }
@@ -221,7 +669,9 @@ impl VM {
                     }
                 },
                 OpCode::Print => {
-                    println!("{}", self.pop());
+                    let line = format!("{}\n", self.pop());
+                    self.output.write_all(line.as_bytes())
+                        .map_err(|e| LoxError::RuntimeError(format!("Could not write output: {}", e)))?;
                 },
                 OpCode::Jump(offset) => {
                     current_frame.ip = unsafe { current_frame.ip.offset(offset as isize) };
@@ -233,6 +683,8 @@ impl VM {
                 },
                 OpCode::Loop(offset) => {
                     current_frame.ip = unsafe { current_frame.ip.offset(-1 - (offset as isize)) };
+                    // Revisiting the loop condition counts as reaching a new line.
+                    current_frame.line = 0;
                 },
                 OpCode::Call(arg_count) => {
                     self.call_value(arg_count as usize)?;
@@ -240,14 +692,17 @@ impl VM {
                     current_block = &current_frame.function.as_ref().unwrap().block;
                 },
                 OpCode::Return => {
+                    for instrument in self.instruments.iter_mut() {
+                        instrument.on_return(current_frame.function.as_ref().unwrap(), self.frame_count);
+                    }
+
                     let result = self.pop();
                     self.frame_count -= 1;
+                    self.stack_top = current_frame.slots;
 
-                    if self.frame_count == 0 {
-                        self.pop();
-                        return Ok(());
+                    if self.frame_count == base {
+                        return Ok(result);
                     } else {
-                        self.stack_top = current_frame.slots;
                         self.push(result);
 
                         current_frame = unsafe { &mut *(&mut self.frames[self.frame_count - 1] as *mut CallFrame) };
@@ -264,8 +719,12 @@ impl VM {
         match callee {
             Value::Function(f) => self.call(f.clone(), arg_count),
             Value::NativeFunction(nf) => {
-                let start = self.stack_top - arg_count;
-                let result = nf.0(self, &self.stack[start..self.stack_top]);
+                nf.check_arity(arg_count)?;
+                // The stack is never reallocated, and natives copy the
+                // arguments they need before calling back into the VM.
+                let args = unsafe { std::slice::from_raw_parts(self.stack.as_ptr().add(self.stack_top - arg_count), arg_count) };
+                let result = (nf.function)(self, args)?;
+                self.allocate_value(&result)?;
                 self.stack_top -= arg_count + 1;
                 self.push(result);
                 Ok(())
@@ -274,9 +733,8 @@ impl VM {
         }
     }
 
-    fn call(&mut self, function: Rc<Function>, arg_count: usize) -> Result<(), LoxError> {
+    fn call(&mut self, function: Arc<Function>, arg_count: usize) -> Result<(), LoxError> {
         if function.arity != arg_count {
-            self.stack_trace();
             Err(LoxError::RuntimeError(format!("Expected {} arguments but got {}", function.arity, arg_count)))
         } else if self.frame_count == VM::MAX_FRAMES {
             Err(LoxError::RuntimeError("Stack overflow".to_string()))
@@ -284,86 +742,182 @@ impl VM {
             let frame = CallFrame::new(function, self.stack_top - arg_count - 1);
             self.frames[self.frame_count] = frame;
             self.frame_count += 1;
+
+            for instrument in self.instruments.iter_mut() {
+                instrument.on_call(self.frames[self.frame_count - 1].function.as_ref().unwrap(), self.frame_count);
+            }
             Ok(())
         }
     }
 
-    fn define_native(&mut self, name: &str, function: NativeFunction) {
-        let name = LoxString::from_string(name);
+    fn define_native(&mut self, function: NativeFunction) {
+        let name = LoxString::from_string(function.name);
         self.globals.set(name, Value::NativeFunction(function));
     }
 
-    fn stack_trace(&self) {
-        for i in (0..self.frame_count).rev() {
-            let frame = &self.frames[i];
-            let function = frame.function.as_ref().unwrap();
-            let offset = unsafe { frame.ip.offset_from(function.block.code.as_ptr()) as usize - 1 };
-            println!("[line {}] in {}", function.block.lines[offset], function.name);
-        }
-    }
+    fn disassemble_instruction(&self, frame: &CallFrame, offset: usize) {
+        let function = frame.function.as_ref().unwrap();
+        let instruction = function.block.code[offset];
+        let text = disassembler::disassemble_instruction(function, offset);
 
-    fn display_jump(&self, block: &Block, instruction: OpCode, offset: usize) {
         match instruction {
-            OpCode::Jump(jump) | OpCode::JumpIfFalse(jump) => {
-                let jump = offset.checked_add_signed(jump as isize).unwrap();
-                println!("{:04} {:?} JUMP_TO: {:04} {:?}", offset, instruction, jump, block.code[jump]);
-            },
-            OpCode::Loop(jump) => {
-                let jump = offset.checked_add_signed(-1 - (jump as isize)).unwrap();
-                println!("{:04} {:?} JUMP_TO: {:04} {:?}", offset, instruction, jump, block.code[jump]);
-            },
-            _ => panic!("Not a jump instruction"),
+            OpCode::DefGlobal(_) | OpCode::SetGlobal(_) | OpCode::SetLocal(_) => println!("{} = {:?}", text, self.peek(0)),
+            OpCode::GetLocal(index) => println!("{} = {:?}", text, self.stack[frame.slots + index as usize]),
+            OpCode::GetGlobal(index) => println!("{} = {:?}", text, self.globals.get(function.block.read_string(index))),
+            _ => println!("{}", text),
         }
     }
+}
 
-    fn disassemble_instruction(&self, frame: &CallFrame, block: &Block, offset: usize) {
-        let line = block.lines[offset];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::Module;
+    use std::thread;
 
-        if offset > 0 && line == block.lines[offset - 1] {
-            print!("   | ");
-        } else {
-            print!("{:04} ", line);
-        }
+    #[test]
+    fn test_instruction_budget() {
+        let mut vm = VM::new();
+        vm.set_instruction_budget(Some(10_000));
 
-        let instruction = block.code[offset];
+        let result = vm.interpret("while (true) {}");
+        assert!(matches!(result, Err(LoxError::InstructionLimit(10_000))));
+        assert!(vm.instruction_count() > 10_000);
 
-        match instruction {
-            OpCode::Constant(index) => {
-                println!("{:04} {:?} IDX: {:4} '{:?}'", offset, instruction, index, block.read_constant(index));           
-            },
-            OpCode::Jump(_) | OpCode::JumpIfFalse(_) | OpCode::Loop(_) => {
-                self.display_jump(block, instruction, offset);
-            },
-            OpCode::DefGlobal(index) => {
-                let name = block.read_string(index);
-                println!("{:04} {:?} IDX: {:4} '{}' = '{:?}'", offset, instruction, index, name.value, self.peek(0));
-            },
-            OpCode::SetLocal(index) => {
-                println!("{:04} {:?} IDX: {:4} = '{:?}'", offset, instruction, index, self.peek(0));
-            },
-            OpCode::GetLocal(index) => {
-                let index = frame.slots + index as usize;
-                println!("{:04} {:?} IDX: {:4} = '{:?}'", offset, instruction, index, self.stack[index]);
-            },
-            OpCode::SetGlobal(index) => {
-                let name = block.read_string(index);
-                println!("{:04} {:?} IDX: {:4} '{}' = '{:?}'", offset, instruction, index, name.value, self.peek(0));
-            },
-            OpCode::GetGlobal(index) => {
-                let name = block.read_string(index);
-                println!("{:04} {:?} IDX: {:4} '{}' = '{:?}'", offset, instruction, index, name.value, self.globals.get(name.clone()));
-            },
-            OpCode::Call(arg_count) => {
-                println!("{:04} {:?} ARGS: {}", offset, instruction, arg_count);
-            },
-            _ => {
-                println!("{:04} {:?}", offset, instruction);
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_instruction_budget_not_reached() {
+        let mut vm = VM::new();
+        vm.set_instruction_budget(Some(10_000));
+
+        assert!(vm.interpret("var x = 0; while (x < 10) { x = x + 1; }").is_ok());
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let mut vm = VM::new();
+        vm.set_time_limit(Some(Duration::from_millis(50)));
+
+        let result = vm.interpret("while (true) {}");
+        assert!(matches!(result, Err(LoxError::Timeout(_))));
+
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut vm = VM::new();
+        let handle = vm.interrupt_handle();
+
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+
+        let result = vm.interpret("while (true) {}");
+        interrupter.join().unwrap();
+        assert!(matches!(result, Err(LoxError::Interrupted)));
+
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_interrupt_before_run() {
+        let mut vm = VM::new();
+        vm.interrupt_handle().interrupt();
+
+        assert!(matches!(vm.interpret("while (true) {}"), Err(LoxError::Interrupted)));
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_reusable_after_runtime_error() {
+        let mut vm = VM::new();
+
+        let result = vm.interpret("fun f() { return -nil; } f();");
+        assert!(matches!(result, Err(LoxError::RuntimeError(_))));
+        assert_eq!(vm.frame_count, 0);
+        assert_eq!(vm.stack_top, 0);
+
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_error_frames() {
+        let mut vm = VM::new();
+
+        assert!(vm.interpret("fun f(a) {}\nfun g() {\n  f();\n}\ng();").is_err());
+        let frames: Vec<(&str, u16)> = vm.error_frames().iter().map(|f| (f.function.as_str(), f.line)).collect();
+        assert_eq!(frames, vec![("g", 3), ("script", 5)]);
+
+        assert!(vm.interpret("fun bad() { f(1, 2); }\nassert_throws(bad);").is_ok());
+        assert!(vm.error_frames().is_empty());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(1 << 20));
+
+        let result = vm.interpret("var s = \"x\"; while (true) { s = s + s; }");
+        assert!(matches!(result, Err(LoxError::OutOfMemory(limit)) if limit == 1 << 20));
+
+        assert!(vm.interpret("var x = 1;").is_ok());
+    }
+
+    #[test]
+    fn test_memory_limit_reclaims_garbage() {
+        let mut vm = VM::new();
+        vm.set_memory_limit(Some(16 * 1024));
+
+        let source = "
+            var s = \"\";
+            for (var i = 0; i < 10000; i = i + 1) {
+                s = \"abc\" + \"def\";
             }
-        }
+        ";
+        assert!(vm.interpret(source).is_ok());
+
+        let stats = vm.memory_stats();
+        assert!(stats.total_allocated > 16 * 1024);
+        assert!(stats.bytes_in_use <= 16 * 1024);
+        assert!(stats.peak_bytes <= 16 * 1024);
     }
-}
 
-fn clock(vm: &VM, _args: &[Value]) -> Value {
-    let elapsed = vm.init_time.elapsed().as_secs_f64();
-    Value::Number(elapsed)
-}
\ No newline at end of file
+    #[test]
+    fn test_memory_stats() {
+        let mut vm = VM::new();
+        let before = vm.memory_stats();
+
+        assert!(vm.interpret("var s = \"hello\" + \" world\"; fun f() {}").is_ok());
+
+        let stats = vm.memory_stats();
+        assert!(stats.bytes_in_use > before.bytes_in_use);
+        assert!(stats.strings_allocated > before.strings_allocated);
+        assert!(stats.functions_allocated >= 2);
+        assert_eq!(stats.limit, None);
+    }
+
+    #[test]
+    fn test_sandboxed_natives() {
+        let mut vm = VM::sandboxed();
+
+        let result = vm.interpret("clock();");
+        assert!(matches!(result, Err(LoxError::RuntimeError(msg)) if msg == "Undefined variable 'clock'"));
+    }
+
+    #[test]
+    fn test_capabilities_exclude_module() {
+        let mut vm = VM::with_capabilities(Capabilities::all().without(Module::Time));
+        assert!(vm.interpret("clock();").is_err());
+    }
+
+    #[test]
+    fn test_capabilities_include_module() {
+        let mut vm = VM::with_capabilities(Capabilities::none().with(Module::Time));
+        assert!(vm.interpret("clock();").is_ok());
+        assert!(vm.capabilities().contains(Module::Time));
+    }
+}
@@ -0,0 +1,149 @@
+//! Runs the interpreter binary to check its command line handling and
+//! exit codes.
+
+// The `debug_trace` feature mixes its trace into the output checked here.
+#![cfg(not(feature = "debug_trace"))]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tapssp-project"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_script_file() {
+    let output = run(&["tests/lox/functions/recursion.lox"], "");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("610\n"));
+    assert!(!stdout(&output).contains("Ok(())"));
+}
+
+#[test]
+fn test_eval_with_args() {
+    let output = run(&["-e", "print args(); print args(0); print args(1);", "first", "--second"], "");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "2\nfirst\n--second\n");
+}
+
+#[test]
+fn test_script_from_stdin() {
+    let output = run(&["-", "x"], "print \"read \" + args(0);\n");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "read x\n");
+}
+
+#[test]
+fn test_filter_piped_input() {
+    let script = "var line = read_line();\nwhile (line != nil) { if (contains(line, \"error\")) print upper(line); line = read_line(); }";
+    let output = run(&["-e", script], "ok\nerror: disk full\nok\nerror: timeout\n");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "ERROR: DISK FULL\nERROR: TIMEOUT\n");
+}
+
+#[test]
+fn test_read_all_piped_input() {
+    let output = run(&["-e", "var text = read_all(); print split(text, \"\n\") - 1; print len(text);"], "a\nbb\nccc\n");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n9\n");
+}
+
+#[test]
+fn test_input_prompt() {
+    let output = run(&["-e", "var name = input(\"Name? \"); var age = num(input(\"Age? \")); print name + \" is \" + str(age + 1) + \" next year\"; print input();"], "Ada\n36\n");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "Name? Age? Ada is 37 next year\nnil\n");
+}
+
+#[test]
+fn test_compile_error_exits_65() {
+    let output = run(&["-e", "print 1 +;"], "");
+
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).contains("Expected expression."));
+}
+
+#[test]
+fn test_runtime_error_exits_70() {
+    let output = run(&["-e", "print 1;\nprint -nil;"], "");
+
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(stdout(&output), "1\n");
+    assert_eq!(stderr(&output), "Operand must be a number\n[line 2] in script\n");
+}
+
+#[test]
+fn test_missing_file() {
+    let output = run(&["does/not/exist.lox"], "");
+
+    assert_eq!(output.status.code(), Some(66));
+    assert!(stderr(&output).starts_with("Could not read does/not/exist.lox: "));
+}
+
+#[test]
+fn test_usage_errors() {
+    assert_eq!(run(&["-e"], "").status.code(), Some(64));
+
+    let output = run(&["--frobnicate"], "");
+    assert_eq!(output.status.code(), Some(64));
+    assert!(stderr(&output).starts_with("Unknown option --frobnicate\n\nUsage: "));
+}
+
+#[test]
+fn test_profile_writes_folded_stacks_only_when_asked() {
+    let folded = std::env::temp_dir().join(format!("lox-cli-{}.folded", std::process::id()));
+    let folded = folded.to_str().unwrap();
+
+    let output = run(&["--profile", "-"], "fun f() {}\nf();\n");
+    assert!(!stderr(&output).contains("Folded stacks"));
+
+    let output = run(&["--profile", "--folded", folded, "-"], "fun f() {}\nf();\n");
+    assert!(stderr(&output).contains(&format!("Folded stacks written to {}", folded)));
+    assert!(std::fs::read_to_string(folded).unwrap().contains("<script>;f 2"));
+    std::fs::remove_file(folded).unwrap();
+
+    let output = run(&["--profile", "-"], "print -nil;\n");
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Operand must be a number\n"));
+}
+
+#[test]
+fn test_coverage_writes_lcov_only_when_asked() {
+    let lcov = std::env::temp_dir().join(format!("lox-cli-{}.info", std::process::id()));
+    let lcov = lcov.to_str().unwrap();
+
+    let output = run(&["--coverage", "tests/lox/functions/recursion.lox"], "");
+    assert!(!stderr(&output).contains("Coverage written"));
+
+    let output = run(&["--coverage", "--coverage-out", lcov, "tests/lox/functions/recursion.lox"], "");
+    assert!(stderr(&output).contains(&format!("Coverage written to {}", lcov)));
+    assert!(std::fs::read_to_string(lcov).unwrap().starts_with("SF:tests/lox/functions/recursion.lox"));
+    std::fs::remove_file(lcov).unwrap();
+
+    let output = run(&["--coverage", "-"], "print -nil;\n");
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Operand must be a number\n"));
+}
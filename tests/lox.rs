@@ -0,0 +1,127 @@
+//! Runs every `.lox` script under `tests/lox` and checks it against the
+//! annotations in its comments:
+//!
+//! - `// expect: <text>` is a line the script prints, in order.
+//! - `// expect runtime error: <message>` is the error the script ends with.
+//! - `// expect compile error: <message>` is a compile error reported on
+//!   the line of the annotation. Scripts with compile errors never run.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tapssp_project::{compiler::analyze, error::LoxError, testing::Capture, vm::VM};
+
+/// What a script prints and how it ends, as expected or as observed.
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    output: Vec<String>,
+    runtime_error: Option<String>,
+    compile_errors: Vec<(usize, String)>,
+}
+
+impl Outcome {
+    fn lines(&self) -> Vec<String> {
+        let mut lines = self.output.clone();
+        lines.extend(self.compile_errors.iter().map(|(line, msg)| format!("[line {}] compile error: {}", line, msg)));
+        lines.extend(self.runtime_error.iter().map(|msg| format!("runtime error: {}", msg)));
+        lines
+    }
+}
+
+fn expectations(source: &str) -> Outcome {
+    let mut expected = Outcome::default();
+
+    for (index, line) in source.lines().enumerate() {
+        if let Some((_, text)) = line.split_once("// expect: ") {
+            expected.output.push(text.to_string());
+        } else if let Some((_, msg)) = line.split_once("// expect runtime error: ") {
+            expected.runtime_error = Some(msg.to_string());
+        } else if let Some((_, msg)) = line.split_once("// expect compile error: ") {
+            expected.compile_errors.push((index + 1, msg.to_string()));
+        }
+    }
+
+    expected
+}
+
+fn run(source: &str) -> Outcome {
+    let mut actual = Outcome::default();
+
+    let analysis = analyze(source);
+    if !analysis.diagnostics.is_empty() {
+        actual.compile_errors = analysis.diagnostics.into_iter().map(|d| (d.line, d.message)).collect();
+        return actual;
+    }
+
+    let buffer = Capture::default();
+    let mut vm = VM::new();
+    vm.set_output(Box::new(buffer.clone()));
+
+    match vm.interpret(source) {
+        Ok(_) => {},
+        Err(LoxError::RuntimeError(msg)) => actual.runtime_error = Some(msg),
+        Err(e) => actual.runtime_error = Some(e.to_string()),
+    }
+
+    let output = buffer.text();
+    actual.output = output.lines().map(str::to_string).collect();
+    actual
+}
+
+/// Expected and actual lines side by side, `-` marking lines only expected
+/// and `+` lines only produced.
+fn diff(expected: &[String], actual: &[String]) -> String {
+    let mut diff = String::new();
+
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => diff.push_str(&format!("   {}\n", e)),
+            (e, a) => {
+                if let Some(e) = e {
+                    diff.push_str(&format!(" - {}\n", e));
+                }
+                if let Some(a) = a {
+                    diff.push_str(&format!(" + {}\n", a));
+                }
+            },
+        }
+    }
+
+    diff
+}
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_lox_scripts() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("lox");
+    let mut paths = Vec::new();
+    scripts(&root, &mut paths);
+    paths.sort();
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let source = fs::read_to_string(path).unwrap();
+        let expected = expectations(&source);
+        let actual = run(&source);
+
+        if actual != expected {
+            let name = path.strip_prefix(&root).unwrap().display();
+            failures.push(format!("{}\n{}", name, diff(&expected.lines(), &actual.lines())));
+        }
+    }
+
+    assert!(!paths.is_empty(), "no scripts found in {}", root.display());
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed:\n\n{}", failures.len(), paths.len(), failures.join("\n")
+    );
+}